@@ -0,0 +1,246 @@
+use crate::event::{ModerationAction, TwitchEvent};
+
+use ansi_term::{Color, Style};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, ToSql};
+use std::{
+    fmt::Display,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    dedup_key TEXT NOT NULL UNIQUE,
+    channel TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    login TEXT,
+    display_name TEXT,
+    content TEXT NOT NULL,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS events_channel_timestamp ON events (channel, timestamp);
+CREATE INDEX IF NOT EXISTS events_channel_login ON events (channel, login, timestamp);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS events_fts
+    USING fts5(content, content = 'events', content_rowid = 'id');
+CREATE TRIGGER IF NOT EXISTS events_after_insert AFTER INSERT ON events BEGIN
+    INSERT INTO events_fts (rowid, content) VALUES (new.id, new.content);
+END;
+";
+
+/// Chat history stored in a SQLite database with full-text search over contents.
+pub struct Archive {
+    conn: Connection,
+}
+
+impl Archive {
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Stores an event. Events already stored, e.g. by another connection to the
+    /// same channel, are ignored.
+    pub fn insert(&self, event: &TwitchEvent) -> rusqlite::Result<()> {
+        let timestamp = event.timestamp().unwrap_or_else(Utc::now);
+        let (kind, login, display_name, content) = match event {
+            TwitchEvent::RoomState(_) => return Ok(()),
+            TwitchEvent::Notice(notice) => ("notice", None, None, notice.message().to_owned()),
+            TwitchEvent::Message(msg) => (
+                if msg.is_action() { "action" } else { "message" },
+                Some(msg.nick_name()),
+                Some(msg.display_name()),
+                msg.content().to_owned(),
+            ),
+            TwitchEvent::UserNotice(notice) => (
+                notice.kind().as_str(),
+                notice.message().map(|msg| msg.nick_name()),
+                notice.message().map(|msg| msg.display_name()),
+                match notice.message() {
+                    Some(msg) => format!("{} {}", notice.system_message(), msg.content()),
+                    None => notice.system_message().to_owned(),
+                },
+            ),
+            TwitchEvent::Moderation(moderation) => {
+                let (kind, login) = match moderation.action() {
+                    ModerationAction::ClearChat => ("clearchat", None),
+                    ModerationAction::Ban { login } => ("ban", Some(login.as_str())),
+                    ModerationAction::Timeout { login, .. } => ("timeout", Some(login.as_str())),
+                    ModerationAction::DeleteMessage { login, .. } => {
+                        ("delete", Some(login.as_str()))
+                    }
+                };
+                (kind, login, None, moderation.to_string())
+            }
+        };
+        let dedup_key = match event.message().and_then(|msg| msg.id()) {
+            Some(id) => id.to_owned(),
+            None => format!(
+                "{}:{}:{}:{}",
+                event.channel(),
+                timestamp.timestamp_millis(),
+                kind,
+                login.unwrap_or_default()
+            ),
+        };
+        let data = serde_json::to_string(event).unwrap();
+
+        self.conn.execute(
+            "INSERT OR IGNORE INTO events
+                (dedup_key, channel, timestamp, kind, login, display_name, content, data)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                dedup_key,
+                event.channel(),
+                timestamp.timestamp_millis(),
+                kind,
+                login.map(str::to_ascii_lowercase),
+                display_name,
+                content,
+                data
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns events matching `query`, oldest first.
+    pub fn search(&self, query: &HistoryQuery) -> rusqlite::Result<Vec<ArchivedEvent>> {
+        let mut sql = "SELECT timestamp, channel, kind, display_name, login, content
+            FROM events WHERE channel = ?"
+            .to_owned();
+        let mut values: Vec<Box<dyn ToSql>> = vec![Box::new(query.channel.to_ascii_lowercase())];
+
+        if let Some(user) = &query.user {
+            sql.push_str(" AND login = ?");
+            values.push(Box::new(user.to_ascii_lowercase()));
+        }
+        if let Some(since) = query.since {
+            sql.push_str(" AND timestamp >= ?");
+            values.push(Box::new(since.timestamp_millis()));
+        }
+        if let Some(text) = &query.text {
+            sql.push_str(" AND id IN (SELECT rowid FROM events_fts WHERE events_fts MATCH ?)");
+            values.push(Box::new(fts_phrases(text)));
+        }
+        sql.push_str(" ORDER BY timestamp DESC LIMIT ?");
+        values.push(Box::new(query.limit as i64));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut events = stmt
+            .query_map(values.iter().map(AsRef::as_ref), |row| {
+                Ok(ArchivedEvent {
+                    timestamp: from_millis(row.get(0)?),
+                    channel: row.get(1)?,
+                    kind: row.get(2)?,
+                    display_name: row.get::<_, Option<String>>(3)?.or(row.get(4)?),
+                    content: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        events.reverse();
+        Ok(events)
+    }
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    (UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64)).into()
+}
+
+/// Quotes each word of `text` so that FTS5 operators in user input are matched literally.
+fn fts_phrases(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct HistoryQuery {
+    pub channel: String,
+    pub user: Option<String>,
+    pub text: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+pub struct ArchivedEvent {
+    pub timestamp: DateTime<Utc>,
+    pub channel: String,
+    pub kind: String,
+    pub display_name: Option<String>,
+    pub content: String,
+}
+
+impl Display for ArchivedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = self.timestamp.format("%Y-%m-%d %H:%M:%S");
+        write!(f, "{} ", Style::new().dimmed().paint(time.to_string()))?;
+
+        match (self.kind.as_str(), &self.display_name) {
+            ("message", Some(name)) => write!(f, "{}: {}", Color::Green.paint(name), self.content),
+            ("action", Some(name)) => write!(
+                f,
+                "{} {}",
+                Color::Green.paint(name),
+                Style::new().italic().paint(&self.content)
+            ),
+            _ => write!(f, "{}", Color::Purple.paint(&self.content)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn insert_and_search() {
+        let archive = Archive::open(":memory:").unwrap();
+        let lines = [
+            "@id=a;tmi-sent-ts=1000;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :hello world",
+            "@id=b;tmi-sent-ts=2000;user-id=2 :baz!baz@baz.tmi.twitch.tv PRIVMSG #bar :hello there",
+            "@id=b;tmi-sent-ts=2000;user-id=2 :baz!baz@baz.tmi.twitch.tv PRIVMSG #bar :hello there",
+            "@id=c;tmi-sent-ts=3000;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #qux :hello world",
+            "@ban-duration=60;tmi-sent-ts=4000 :tmi.twitch.tv CLEARCHAT #bar :foo",
+        ];
+        for line in &lines {
+            let event = TwitchEvent::try_from(irc::proto::Message::from(*line)).unwrap();
+            archive.insert(&event).unwrap();
+        }
+
+        let mut query = HistoryQuery {
+            channel: "bar".to_owned(),
+            user: None,
+            text: Some("hello".to_owned()),
+            since: None,
+            limit: 10,
+        };
+        let contents = |query: &HistoryQuery| -> Vec<String> {
+            archive
+                .search(query)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.content)
+                .collect()
+        };
+        assert_eq!(contents(&query), ["hello world", "hello there"]);
+
+        query.user = Some("FOO".to_owned());
+        query.text = None;
+        assert_eq!(
+            contents(&query),
+            ["hello world", "foo has been timed out for 60s"]
+        );
+
+        query.since = Some(from_millis(1500));
+        query.text = Some("\"world".to_owned());
+        assert!(contents(&query).is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{helix, message::Badge, Auth};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+struct QueryParams<'a> {
+    broadcaster_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct BadgesResponse {
+    data: Vec<BadgeSet>,
+}
+
+#[derive(Deserialize)]
+struct BadgeSet {
+    set_id: String,
+    versions: Vec<BadgeVersion>,
+}
+
+#[derive(Deserialize)]
+struct BadgeVersion {
+    id: String,
+    image_url_1x: String,
+    title: Option<String>,
+}
+
+/// Images of the badges that can appear in a channel's chat.
+#[derive(Clone, Debug, Default)]
+pub struct ChatBadges(HashMap<(String, String), (String, Option<String>)>);
+
+impl ChatBadges {
+    pub fn image_url(&self, badge: &Badge) -> Option<&str> {
+        self.get(badge).map(|(url, _)| url.as_str())
+    }
+
+    /// Human-readable name of the badge, e.g. "6-Month Subscriber".
+    pub fn title(&self, badge: &Badge) -> Option<&str> {
+        self.get(badge).and_then(|(_, title)| title.as_deref())
+    }
+
+    fn get(&self, badge: &Badge) -> Option<&(String, Option<String>)> {
+        self.0
+            .get(&(badge.set_id().to_owned(), badge.version().to_owned()))
+    }
+
+    fn extend(&mut self, response: BadgesResponse) {
+        for set in response.data {
+            for version in set.versions {
+                self.0.insert(
+                    (set.set_id.clone(), version.id),
+                    (version.image_url_1x, version.title),
+                );
+            }
+        }
+    }
+}
+
+/// Fetches the global badges along with those of the channel with
+/// `broadcaster_id`, which take precedence.
+pub async fn get_chat_badges(broadcaster_id: &str, auth: &Auth) -> actix_web::Result<ChatBadges> {
+    let query = QueryParams { broadcaster_id };
+    let (global, channel) = futures::try_join!(
+        helix::get("chat/badges/global", &(), auth),
+        helix::get("chat/badges", &query, auth),
+    )?;
+
+    let mut badges = ChatBadges::default();
+    badges.extend(global);
+    badges.extend(channel);
+    Ok(badges)
+}
@@ -0,0 +1,173 @@
+use crate::{get_user, helix, Auth};
+
+use ansi_term::{Color, Style};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, time::Duration};
+
+#[derive(Serialize)]
+struct ChannelsQueryParams<'a> {
+    broadcaster_id: &'a str,
+}
+
+#[derive(Serialize)]
+struct StreamsQueryParams<'a> {
+    user_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct Channel {
+    game_name: String,
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct LiveStream {
+    started_at: DateTime<Utc>,
+    viewer_count: usize,
+}
+
+/// Who a channel belongs to, what it is about and whether it is live.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelInfo {
+    id: String,
+    login: String,
+    display_name: String,
+    game_name: String,
+    title: String,
+    live: Option<LiveStatus>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LiveStatus {
+    pub started_at: DateTime<Utc>,
+    pub viewer_count: usize,
+}
+
+impl ChannelInfo {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn game_name(&self) -> &str {
+        &self.game_name
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Returns `None` if the channel is offline.
+    pub fn live(&self) -> Option<&LiveStatus> {
+        self.live.as_ref()
+    }
+}
+
+impl Display for ChannelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} /{} ",
+            Color::Green.paint(&self.display_name),
+            self.login
+        )?;
+
+        match &self.live {
+            Some(live) => {
+                let uptime = (Utc::now() - live.started_at).num_minutes().max(0);
+                write!(
+                    f,
+                    "{} for {}h{:02}m ({} viewers)",
+                    Color::Red.bold().paint("LIVE"),
+                    uptime / 60,
+                    uptime % 60,
+                    live.viewer_count
+                )?;
+            }
+            None => write!(f, "{}", Style::new().dimmed().paint("offline"))?,
+        }
+
+        if !self.game_name.is_empty() {
+            write!(f, " - {}", Color::Blue.paint(&self.game_name))?;
+        }
+        let title = self.title.trim();
+        if !title.is_empty() {
+            write!(f, " - {}", title)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Looks up a channel by login name, returning `None` if there is no such channel.
+pub async fn get_channel_info(login: &str, auth: &Auth) -> actix_web::Result<Option<ChannelInfo>> {
+    let user = match get_user(login, auth).await? {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+
+    let channels_query = ChannelsQueryParams {
+        broadcaster_id: user.id(),
+    };
+    let streams_query = StreamsQueryParams { user_id: user.id() };
+    let (channels, streams): (Response<Channel>, Response<LiveStream>) = futures::try_join!(
+        helix::get("channels", &channels_query, auth),
+        helix::get("streams", &streams_query, auth),
+    )?;
+    let channel = channels.data.into_iter().next();
+
+    Ok(Some(ChannelInfo {
+        id: user.id().to_owned(),
+        login: user.login().to_owned(),
+        display_name: user.display_name().to_owned(),
+        game_name: channel
+            .as_ref()
+            .map(|c| c.game_name.clone())
+            .unwrap_or_default(),
+        title: channel.map(|c| c.title).unwrap_or_default(),
+        live: streams.data.into_iter().next().map(|stream| LiveStatus {
+            started_at: stream.started_at,
+            viewer_count: stream.viewer_count,
+        }),
+    }))
+}
+
+/// Fetches the info of a channel every `period`, starting one period from now,
+/// or never if `period` is zero. Lookups of channels that no longer exist are
+/// skipped.
+pub fn watch_channel_info(
+    login: &str,
+    period: Duration,
+    auth: &Auth,
+) -> impl Stream<Item = actix_web::Result<ChannelInfo>> {
+    let login = login.to_owned();
+    let auth = auth.clone();
+    let start = actix_web::rt::time::Instant::now() + period;
+    // `interval_at` panics on a zero period.
+    let ticks = Some(period)
+        .filter(|period| *period > Duration::from_secs(0))
+        .map(|period| actix_web::rt::time::interval_at(start, period));
+
+    futures::stream::iter(ticks)
+        .flatten()
+        .then(move |_| {
+            let login = login.clone();
+            let auth = auth.clone();
+            async move { get_channel_info(&login, &auth).await }
+        })
+        .filter_map(|result| futures::future::ready(result.transpose()))
+}
@@ -1,63 +1,179 @@
-use crate::message::{FallbackColor, TwitchMessage};
+use crate::{
+    event::{Notice, RoomState, TwitchEvent},
+    message::FallbackColorMap,
+};
 
 use futures::{Stream, StreamExt};
 use irc::client::{prelude::*, ClientStream};
-use rand::Rng;
 use std::{
-    collections::HashMap,
     convert::TryFrom,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
+use thiserror::Error;
+
+/// How long to wait for the initial ROOMSTATE after joining a channel.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// NOTICE ids meaning that the channel cannot be joined, or is no longer available.
+const JOIN_FAILURES: &[&str] = &[
+    "msg_banned",
+    "msg_channel_blocked",
+    "msg_channel_suspended",
+    "tos_ban",
+];
+
+#[derive(Debug, Error)]
+pub enum ChannelError {
+    #[error(transparent)]
+    Irc(#[from] irc::error::Error),
+    #[error("Login failed: {0}")]
+    LoginFailed(String),
+    #[error("Cannot join #{}: {}", .0.channel(), .0.message())]
+    JoinFailed(Notice),
+    #[error("Timed out joining #{0}, the channel probably does not exist")]
+    JoinTimeout(String),
+    #[error("Connection closed")]
+    Disconnected,
+}
 
 pub struct TwitchChannelStream {
     client_stream: ClientStream,
-    color_map: HashMap<u64, FallbackColor>,
+    sender: Sender,
+    color_map: FallbackColorMap,
+    room_state: RoomState,
+    has_quit: bool,
+    is_terminated: bool,
 }
 
 impl TwitchChannelStream {
-    pub async fn new(channel: &str) -> irc::error::Result<Self> {
+    /// Connects and waits until the channel has been joined.
+    pub async fn new(channel: &str) -> Result<Self, ChannelError> {
         let channel = format!("#{}", channel.to_ascii_lowercase());
 
         let config = Config {
             server: Some("irc.chat.twitch.tv".to_owned()),
             nickname: Some("justinfan1234".to_owned()),
-            channels: vec![channel],
+            channels: vec![channel.clone()],
             ..Config::default()
         };
 
         let mut client = Client::from_config(config).await?;
-        client.send_cap_req(&[Capability::Custom("twitch.tv/tags")])?;
+        client.send_cap_req(&[
+            Capability::Custom("twitch.tv/tags"),
+            Capability::Custom("twitch.tv/commands"),
+        ])?;
         client.identify()?;
 
-        Ok(Self {
+        let mut stream = Self {
             client_stream: client.stream()?,
-            color_map: HashMap::new(),
-        })
+            sender: client.sender(),
+            color_map: FallbackColorMap::default(),
+            room_state: RoomState::new(&channel),
+            has_quit: false,
+            is_terminated: false,
+        };
+
+        // Twitch sends the full ROOMSTATE once the channel has been joined, and
+        // nothing at all for channels that do not exist.
+        actix_web::rt::time::timeout(JOIN_TIMEOUT, stream.wait_for_join())
+            .await
+            .map_err(|_| ChannelError::JoinTimeout(stream.room_state.channel.clone()))??;
+
+        Ok(stream)
+    }
+
+    async fn wait_for_join(&mut self) -> Result<(), ChannelError> {
+        while let Some(msg) = self.client_stream.next().await {
+            if let Some(TwitchEvent::RoomState(_)) = self.handle(msg?)? {
+                return Ok(());
+            }
+        }
+        Err(ChannelError::Disconnected)
+    }
+
+    pub fn room_state(&self) -> &RoomState {
+        &self.room_state
+    }
+
+    /// Leaves the channel and disconnects. The stream keeps yielding events
+    /// until the server closes the connection, and then ends without an error.
+    pub fn quit(&mut self) -> Result<(), ChannelError> {
+        self.sender
+            .send(Command::PART(self.room_state.channel.clone(), None))?;
+        self.sender.send(Command::QUIT(None))?;
+        self.has_quit = true;
+        Ok(())
+    }
+
+    /// Turns a message into an event, or an error if it ends the stream.
+    fn handle(&mut self, msg: Message) -> Result<Option<TwitchEvent>, ChannelError> {
+        if let Command::Raw(command, _) = &msg.command {
+            if command == "ROOMSTATE" {
+                let prev = self.room_state.clone();
+                if let Some(tags) = &msg.tags {
+                    // Keep the settings applied so far rather than ending the stream
+                    if let Err(e) = self.room_state.update(tags) {
+                        log::warn!("Invalid ROOMSTATE in #{}: {}", self.room_state.channel, e);
+                    }
+                }
+                // The initial ROOMSTATE always counts as a change since it sets the room id.
+                if self.room_state == prev {
+                    return Ok(None);
+                }
+                return Ok(Some(TwitchEvent::RoomState(self.room_state.clone())));
+            }
+        }
+
+        let mut event = match TwitchEvent::try_from(msg) {
+            Ok(event) => event,
+            Err(_) => return Ok(None),
+        };
+        if let TwitchEvent::Notice(notice) = &event {
+            match notice.msg_id() {
+                None if notice.channel() == "*" => {
+                    return Err(ChannelError::LoginFailed(notice.message().to_owned()));
+                }
+                Some(msg_id) if JOIN_FAILURES.contains(&msg_id) => {
+                    return Err(ChannelError::JoinFailed(notice.clone()));
+                }
+                _ => (),
+            }
+        }
+        if let Some(msg) = event.message_mut() {
+            self.color_map.fill(msg);
+        }
+        Ok(Some(event))
     }
 }
 
 impl Stream for TwitchChannelStream {
-    type Item = TwitchMessage;
+    type Item = Result<TwitchEvent, ChannelError>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.is_terminated {
+            return Poll::Ready(None);
+        }
+
         while let Poll::Ready(maybe_msg) = self.client_stream.poll_next_unpin(cx) {
-            match maybe_msg {
-                Some(Ok(msg)) => {
-                    if let Ok(mut msg) = TwitchMessage::try_from(msg) {
-                        if msg.color().is_none() {
-                            let color = *self
-                                .color_map
-                                .entry(msg.user_id())
-                                .or_insert_with(|| rand::thread_rng().gen());
-                            msg.set_color(color.into());
-                        }
-                        return Poll::Ready(Some(msg));
-                    }
-                }
-                Some(Err(_)) | None => {
+            let result = match maybe_msg {
+                Some(msg) => msg
+                    .map_err(ChannelError::from)
+                    .and_then(|msg| self.handle(msg)),
+                None if self.has_quit => {
+                    self.is_terminated = true;
                     return Poll::Ready(None);
                 }
+                None => Err(ChannelError::Disconnected),
+            };
+            match result {
+                Ok(Some(event)) => return Poll::Ready(Some(Ok(event))),
+                Ok(None) => (),
+                Err(e) => {
+                    self.is_terminated = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
             }
         }
 
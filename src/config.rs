@@ -0,0 +1,615 @@
+use crate::{credentials::Credentials, filter::EventFilter, message::ColorMode, oauth, Auth};
+
+use serde::Deserialize;
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read {}: {}", .0.display(), .1)]
+    Io(PathBuf, #[source] io::Error),
+    #[error("Invalid config file {}: {}", .0.display(), .1)]
+    Parse(PathBuf, #[source] toml::de::Error),
+    #[error("Invalid value of {0}: {1}")]
+    Env(&'static str, String),
+    #[error("Missing {0}, set it in the config file or the {1} environment variable")]
+    Missing(&'static str, &'static str),
+}
+
+/// Settings read from a TOML file, with environment variables layered on top.
+///
+/// ```toml
+/// channels = ["foo", "bar"]
+/// archive_path = "/var/lib/twch/archive.db"
+///
+/// [auth]
+/// client_id = "..."
+/// oauth_token = "..."
+/// credentials_path = "/var/lib/twch/credentials.toml"
+///
+/// [display]
+/// color = "256"
+/// width = 100
+/// filter = "highlighted,first"
+/// highlights = ["foo"]
+///
+/// [limits]
+/// list = 20
+///
+/// [server]
+/// http_addr = "127.0.0.1:8080"
+/// heartbeat_interval = 10
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub auth: AuthConfig,
+    /// Channels to use when none are given on the command line.
+    pub channels: Vec<String>,
+    pub archive_path: Option<PathBuf>,
+    /// Recent-messages service used to show chats sent before joining, e.g.
+    /// `https://recent-messages.robotty.de/api/v2/recent-messages`.
+    pub recent_messages_url: Option<String>,
+    /// Server providing the v5 `videos/{id}/comments` endpoint used by
+    /// `twch vod`. Twitch shut down its own in 2022, so there is no default.
+    pub vod_api_url: Option<String>,
+    pub display: DisplayConfig,
+    pub limits: LimitsConfig,
+    pub server: ServerConfig,
+    #[serde(skip)]
+    from_env: BTreeSet<&'static str>,
+    #[serde(skip)]
+    credentials: Option<Credentials>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub client_id: Option<String>,
+    pub oauth_token: Option<String>,
+    /// Base URL of the OAuth server used by `twch login`.
+    pub auth_url: Option<String>,
+    /// Where `twch login` stores tokens.
+    pub credentials_path: Option<PathBuf>,
+}
+
+impl AuthConfig {
+    pub fn auth_url(&self) -> &str {
+        self.auth_url.as_deref().unwrap_or(oauth::DEFAULT_AUTH_URL)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    pub color: Option<ColorMode>,
+    pub width: Option<usize>,
+    pub filter: Option<EventFilter>,
+    pub highlights: Vec<String>,
+    pub timestamps: bool,
+    pub badges: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub list: Option<usize>,
+    pub search: Option<usize>,
+    pub stats: Option<usize>,
+    pub history: Option<usize>,
+}
+
+impl LimitsConfig {
+    pub fn list(&self) -> usize {
+        self.list.unwrap_or(10)
+    }
+
+    pub fn search(&self) -> usize {
+        self.search.unwrap_or(10)
+    }
+
+    pub fn stats(&self) -> usize {
+        self.stats.unwrap_or(10)
+    }
+
+    pub fn history(&self) -> usize {
+        self.history.unwrap_or(100)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub http_addr: Option<String>,
+    /// In seconds.
+    pub heartbeat_interval: Option<u64>,
+    /// In seconds, or 0 to never refresh channel info.
+    pub info_refresh_interval: Option<u64>,
+    /// How long stream and game lists are cached, in seconds.
+    pub cache_ttl: Option<u64>,
+    /// How long an expired list may still be served while it is refreshed, in
+    /// seconds.
+    pub cache_stale: Option<u64>,
+    /// Requests allowed per client address and minute, or 0 for no limit.
+    pub requests_per_minute: Option<u32>,
+    /// Concurrent chat streams allowed in total.
+    pub max_streams: Option<usize>,
+    /// Concurrent chat streams allowed per client address.
+    pub max_streams_per_ip: Option<usize>,
+    /// Take client addresses from `Forwarded` or `X-Forwarded-For`, which is
+    /// only safe behind a reverse proxy that sets them.
+    pub trust_proxy: bool,
+    /// How long streams may take to close on shutdown, in seconds.
+    pub shutdown_timeout: Option<u64>,
+    /// Recent events kept per channel and replayed to new clients, or 0 for none.
+    pub backlog_size: Option<usize>,
+    /// PEM certificate chain to serve HTTPS with, along with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// Address of a plain HTTP listener redirecting to HTTPS.
+    pub redirect_addr: Option<String>,
+}
+
+impl ServerConfig {
+    pub fn http_addr(&self) -> &str {
+        self.http_addr.as_deref().unwrap_or("0.0.0.0:8080")
+    }
+
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_interval.unwrap_or(10))
+    }
+
+    pub fn info_refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.info_refresh_interval.unwrap_or(300))
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl.unwrap_or(30))
+    }
+
+    pub fn cache_stale(&self) -> Duration {
+        Duration::from_secs(self.cache_stale.unwrap_or(60))
+    }
+
+    pub fn requests_per_minute(&self) -> u32 {
+        self.requests_per_minute.unwrap_or(60)
+    }
+
+    pub fn max_streams(&self) -> usize {
+        self.max_streams.unwrap_or(100)
+    }
+
+    pub fn max_streams_per_ip(&self) -> usize {
+        self.max_streams_per_ip.unwrap_or(3)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout.unwrap_or(10))
+    }
+
+    pub fn backlog_size(&self) -> usize {
+        self.backlog_size.unwrap_or(50)
+    }
+
+    /// Paths of the certificate chain and private key, if HTTPS is enabled.
+    pub fn tls(&self) -> Result<Option<(&Path, &Path)>, ConfigError> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Ok(Some((cert, key))),
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(ConfigError::Missing("server.tls_key", "TLS_KEY")),
+            (None, Some(_)) => Err(ConfigError::Missing("server.tls_cert", "TLS_CERT")),
+        }
+    }
+}
+
+/// Where the effective value of a setting comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    File,
+    Env,
+    Credentials,
+}
+
+impl Config {
+    /// Reads `path` and applies environment variables.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.into(), e))?;
+        let mut config: Self =
+            toml::from_str(&s).map_err(|e| ConfigError::Parse(path.into(), e))?;
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Uses only environment variables.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        fn var<T: FromStr>(name: &'static str) -> Result<Option<T>, ConfigError> {
+            match std::env::var(name) {
+                Ok(value) => value
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| ConfigError::Env(name, value)),
+                Err(_) => Ok(None),
+            }
+        }
+
+        let client_id = var::<String>("CLIENT_ID")?;
+        let oauth_token = var::<String>("OAUTH_TOKEN")?;
+        let auth_url = var::<String>("AUTH_URL")?;
+        let archive_path = var::<PathBuf>("ARCHIVE_PATH")?;
+        let recent_messages_url = var::<String>("RECENT_MESSAGES_URL")?;
+        let vod_api_url = var::<String>("VOD_API_URL")?;
+        let no_color = std::env::var_os("NO_COLOR").is_some();
+        let http_addr = var::<String>("HTTP_ADDR")?;
+        let heartbeat_interval = var::<u64>("HEARTBEAT_INTERVAL")?;
+        let info_refresh_interval = var::<u64>("INFO_REFRESH_INTERVAL")?;
+        let tls_cert = var::<PathBuf>("TLS_CERT")?;
+        let tls_key = var::<PathBuf>("TLS_KEY")?;
+        let redirect_addr = var::<String>("REDIRECT_ADDR")?;
+        let cache_ttl = var::<u64>("CACHE_TTL")?;
+        let cache_stale = var::<u64>("CACHE_STALE")?;
+        let backlog_size = var::<usize>("BACKLOG_SIZE")?;
+        let requests_per_minute = var::<u32>("REQUESTS_PER_MINUTE")?;
+        let max_streams = var::<usize>("MAX_STREAMS")?;
+        let max_streams_per_ip = var::<usize>("MAX_STREAMS_PER_IP")?;
+        let trust_proxy = var::<bool>("TRUST_PROXY")?;
+
+        let applied = [
+            ("CLIENT_ID", client_id.is_some()),
+            ("OAUTH_TOKEN", oauth_token.is_some()),
+            ("AUTH_URL", auth_url.is_some()),
+            ("ARCHIVE_PATH", archive_path.is_some()),
+            ("RECENT_MESSAGES_URL", recent_messages_url.is_some()),
+            ("VOD_API_URL", vod_api_url.is_some()),
+            ("NO_COLOR", no_color),
+            ("HTTP_ADDR", http_addr.is_some()),
+            ("HEARTBEAT_INTERVAL", heartbeat_interval.is_some()),
+            ("INFO_REFRESH_INTERVAL", info_refresh_interval.is_some()),
+            ("TLS_CERT", tls_cert.is_some()),
+            ("TLS_KEY", tls_key.is_some()),
+            ("REDIRECT_ADDR", redirect_addr.is_some()),
+            ("CACHE_TTL", cache_ttl.is_some()),
+            ("CACHE_STALE", cache_stale.is_some()),
+            ("BACKLOG_SIZE", backlog_size.is_some()),
+            ("REQUESTS_PER_MINUTE", requests_per_minute.is_some()),
+            ("MAX_STREAMS", max_streams.is_some()),
+            ("MAX_STREAMS_PER_IP", max_streams_per_ip.is_some()),
+            ("TRUST_PROXY", trust_proxy.is_some()),
+        ];
+        for (name, _) in applied.iter().filter(|(_, applied)| *applied) {
+            self.from_env.insert(name);
+        }
+
+        self.auth.client_id = client_id.or_else(|| self.auth.client_id.take());
+        self.auth.oauth_token = oauth_token.or_else(|| self.auth.oauth_token.take());
+        self.auth.auth_url = auth_url.or_else(|| self.auth.auth_url.take());
+        self.archive_path = archive_path.or_else(|| self.archive_path.take());
+        self.recent_messages_url = recent_messages_url.or_else(|| self.recent_messages_url.take());
+        self.vod_api_url = vod_api_url.or_else(|| self.vod_api_url.take());
+        if no_color {
+            self.display.color = Some(ColorMode::None);
+        }
+        self.server.http_addr = http_addr.or_else(|| self.server.http_addr.take());
+        self.server.heartbeat_interval = heartbeat_interval.or(self.server.heartbeat_interval);
+        self.server.info_refresh_interval =
+            info_refresh_interval.or(self.server.info_refresh_interval);
+        self.server.tls_cert = tls_cert.or_else(|| self.server.tls_cert.take());
+        self.server.tls_key = tls_key.or_else(|| self.server.tls_key.take());
+        self.server.redirect_addr = redirect_addr.or_else(|| self.server.redirect_addr.take());
+        self.server.cache_ttl = cache_ttl.or(self.server.cache_ttl);
+        self.server.cache_stale = cache_stale.or(self.server.cache_stale);
+        self.server.backlog_size = backlog_size.or(self.server.backlog_size);
+        self.server.requests_per_minute = requests_per_minute.or(self.server.requests_per_minute);
+        self.server.max_streams = max_streams.or(self.server.max_streams);
+        self.server.max_streams_per_ip = max_streams_per_ip.or(self.server.max_streams_per_ip);
+        if let Some(trust_proxy) = trust_proxy {
+            self.server.trust_proxy = trust_proxy;
+        }
+        Ok(())
+    }
+
+    /// Reads the tokens stored by `twch login` from `auth.credentials_path`, or
+    /// `default_path` if that is not set. They are used by [`Config::auth`] when
+    /// no token is configured.
+    pub fn load_credentials(&mut self, default_path: Option<&Path>) -> Result<(), ConfigError> {
+        let path = match self.auth.credentials_path.as_deref().or(default_path) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.credentials = Credentials::load(path).map_err(|e| ConfigError::Io(path.into(), e))?;
+        Ok(())
+    }
+
+    pub fn credentials(&self) -> Option<&Credentials> {
+        self.credentials.as_ref()
+    }
+
+    /// Replaces the stored credentials, e.g. after refreshing the token.
+    pub fn set_credentials(&mut self, credentials: Credentials) {
+        self.credentials = Some(credentials);
+    }
+
+    pub fn auth(&self) -> Result<Auth, ConfigError> {
+        if let (None, Some(credentials)) = (&self.auth.oauth_token, &self.credentials) {
+            return Ok(credentials.auth());
+        }
+        let client_id = self
+            .auth
+            .client_id
+            .as_deref()
+            .ok_or(ConfigError::Missing("auth.client_id", "CLIENT_ID"))?;
+        let oauth_token = self
+            .auth
+            .oauth_token
+            .as_deref()
+            .ok_or(ConfigError::Missing("auth.oauth_token", "OAUTH_TOKEN"))?;
+        Ok(Auth::new(client_id, oauth_token))
+    }
+
+    /// Lists every setting with its effective value, if any, and where the value
+    /// comes from. Secrets are masked.
+    pub fn entries(&self) -> Vec<(&'static str, Option<String>, Source)> {
+        let source = |in_file: bool, env: Option<&str>| {
+            if env.map_or(false, |env| self.from_env.contains(env)) {
+                Source::Env
+            } else if in_file {
+                Source::File
+            } else {
+                Source::Default
+            }
+        };
+        let list = |values: &[String]| Some(values.join(", ")).filter(|s| !s.is_empty());
+        let credentials = self
+            .credentials
+            .as_ref()
+            .filter(|_| self.auth.oauth_token.is_none());
+
+        vec![
+            match credentials {
+                Some(credentials) => (
+                    "auth.client_id",
+                    Some(credentials.client_id.clone()),
+                    Source::Credentials,
+                ),
+                None => (
+                    "auth.client_id",
+                    self.auth.client_id.clone(),
+                    source(self.auth.client_id.is_some(), Some("CLIENT_ID")),
+                ),
+            },
+            match credentials {
+                Some(_) => (
+                    "auth.oauth_token",
+                    Some("********".to_owned()),
+                    Source::Credentials,
+                ),
+                None => (
+                    "auth.oauth_token",
+                    self.auth
+                        .oauth_token
+                        .as_ref()
+                        .map(|_| "********".to_owned()),
+                    source(self.auth.oauth_token.is_some(), Some("OAUTH_TOKEN")),
+                ),
+            },
+            (
+                "auth.auth_url",
+                Some(self.auth.auth_url().to_owned()),
+                source(self.auth.auth_url.is_some(), Some("AUTH_URL")),
+            ),
+            (
+                "auth.credentials_path",
+                self.auth
+                    .credentials_path
+                    .as_ref()
+                    .map(|p| p.display().to_string()),
+                source(self.auth.credentials_path.is_some(), None),
+            ),
+            (
+                "channels",
+                list(&self.channels),
+                source(!self.channels.is_empty(), None),
+            ),
+            (
+                "archive_path",
+                self.archive_path.as_ref().map(|p| p.display().to_string()),
+                source(self.archive_path.is_some(), Some("ARCHIVE_PATH")),
+            ),
+            (
+                "recent_messages_url",
+                self.recent_messages_url.clone(),
+                source(
+                    self.recent_messages_url.is_some(),
+                    Some("RECENT_MESSAGES_URL"),
+                ),
+            ),
+            (
+                "vod_api_url",
+                self.vod_api_url.clone(),
+                source(self.vod_api_url.is_some(), Some("VOD_API_URL")),
+            ),
+            (
+                "display.color",
+                Some(self.display.color.unwrap_or_default().to_string()),
+                source(self.display.color.is_some(), Some("NO_COLOR")),
+            ),
+            (
+                "display.width",
+                self.display.width.map(|width| width.to_string()),
+                source(self.display.width.is_some(), None),
+            ),
+            (
+                "display.filter",
+                self.display.filter.as_ref().map(ToString::to_string),
+                source(self.display.filter.is_some(), None),
+            ),
+            (
+                "display.highlights",
+                list(&self.display.highlights),
+                source(!self.display.highlights.is_empty(), None),
+            ),
+            (
+                "display.timestamps",
+                Some(self.display.timestamps.to_string()),
+                source(self.display.timestamps, None),
+            ),
+            (
+                "display.badges",
+                Some(self.display.badges.to_string()),
+                source(self.display.badges, None),
+            ),
+            (
+                "limits.list",
+                Some(self.limits.list().to_string()),
+                source(self.limits.list.is_some(), None),
+            ),
+            (
+                "limits.search",
+                Some(self.limits.search().to_string()),
+                source(self.limits.search.is_some(), None),
+            ),
+            (
+                "limits.stats",
+                Some(self.limits.stats().to_string()),
+                source(self.limits.stats.is_some(), None),
+            ),
+            (
+                "limits.history",
+                Some(self.limits.history().to_string()),
+                source(self.limits.history.is_some(), None),
+            ),
+            (
+                "server.http_addr",
+                Some(self.server.http_addr().to_owned()),
+                source(self.server.http_addr.is_some(), Some("HTTP_ADDR")),
+            ),
+            (
+                "server.heartbeat_interval",
+                Some(self.server.heartbeat_interval().as_secs().to_string()),
+                source(
+                    self.server.heartbeat_interval.is_some(),
+                    Some("HEARTBEAT_INTERVAL"),
+                ),
+            ),
+            (
+                "server.info_refresh_interval",
+                Some(self.server.info_refresh_interval().as_secs().to_string()),
+                source(
+                    self.server.info_refresh_interval.is_some(),
+                    Some("INFO_REFRESH_INTERVAL"),
+                ),
+            ),
+            (
+                "server.cache_ttl",
+                Some(self.server.cache_ttl().as_secs().to_string()),
+                source(self.server.cache_ttl.is_some(), Some("CACHE_TTL")),
+            ),
+            (
+                "server.cache_stale",
+                Some(self.server.cache_stale().as_secs().to_string()),
+                source(self.server.cache_stale.is_some(), Some("CACHE_STALE")),
+            ),
+            (
+                "server.requests_per_minute",
+                Some(self.server.requests_per_minute().to_string()),
+                source(
+                    self.server.requests_per_minute.is_some(),
+                    Some("REQUESTS_PER_MINUTE"),
+                ),
+            ),
+            (
+                "server.max_streams",
+                Some(self.server.max_streams().to_string()),
+                source(self.server.max_streams.is_some(), Some("MAX_STREAMS")),
+            ),
+            (
+                "server.max_streams_per_ip",
+                Some(self.server.max_streams_per_ip().to_string()),
+                source(
+                    self.server.max_streams_per_ip.is_some(),
+                    Some("MAX_STREAMS_PER_IP"),
+                ),
+            ),
+            (
+                "server.trust_proxy",
+                Some(self.server.trust_proxy.to_string()),
+                source(self.server.trust_proxy, Some("TRUST_PROXY")),
+            ),
+            (
+                "server.shutdown_timeout",
+                Some(self.server.shutdown_timeout().as_secs().to_string()),
+                source(self.server.shutdown_timeout.is_some(), None),
+            ),
+            (
+                "server.backlog_size",
+                Some(self.server.backlog_size().to_string()),
+                source(self.server.backlog_size.is_some(), Some("BACKLOG_SIZE")),
+            ),
+            (
+                "server.tls_cert",
+                self.server
+                    .tls_cert
+                    .as_ref()
+                    .map(|p| p.display().to_string()),
+                source(self.server.tls_cert.is_some(), Some("TLS_CERT")),
+            ),
+            (
+                "server.tls_key",
+                self.server
+                    .tls_key
+                    .as_ref()
+                    .map(|p| p.display().to_string()),
+                source(self.server.tls_key.is_some(), Some("TLS_KEY")),
+            ),
+            (
+                "server.redirect_addr",
+                self.server.redirect_addr.clone(),
+                source(self.server.redirect_addr.is_some(), Some("REDIRECT_ADDR")),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            channels = ["foo"]
+
+            [display]
+            color = "256"
+            filter = "highlighted,first"
+
+            [limits]
+            history = 50
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.channels, ["foo"]);
+        assert_eq!(config.display.color, Some(ColorMode::Ansi256));
+        assert!(config.display.filter.unwrap().highlighted);
+        assert_eq!(config.limits.history(), 50);
+        assert_eq!(config.limits.list(), 10);
+
+        assert!(toml::from_str::<Config>("colour = \"none\"").is_err());
+        assert!(toml::from_str::<Config>("[display]\ncolor = \"red\"").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Auth;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+/// Tokens obtained with `twch login`, kept in a file only the user can read.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Credentials {
+    pub client_id: String,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub login: Option<String>,
+}
+
+impl Credentials {
+    /// Returns `None` if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Option<Self>> {
+        let s = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        toml::from_str(&s)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let s = toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // `mode` only applies to newly created files.
+            if path.exists() {
+                fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            }
+        }
+        options.open(path)?.write_all(s.as_bytes())
+    }
+
+    pub fn remove<P: AsRef<Path>>(path: P) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn auth(&self) -> Auth {
+        Auth::new(&self.client_id, &self.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir()
+            .join(format!("twch-test-{}", std::process::id()))
+            .join("credentials.toml");
+        assert!(Credentials::load(&path).unwrap().is_none());
+
+        let credentials = Credentials {
+            client_id: "abc".to_owned(),
+            access_token: "xyz".to_owned(),
+            refresh_token: None,
+            login: Some("foo".to_owned()),
+        };
+        credentials.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let loaded = Credentials::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.access_token, "xyz");
+        assert_eq!(loaded.login.as_deref(), Some("foo"));
+
+        Credentials::remove(&path).unwrap();
+        assert!(Credentials::load(&path).unwrap().is_none());
+        fs::remove_dir(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,572 @@
+use crate::{
+    message::{parse_timestamp, time_label, ParseError, RenderOptions, TwitchMessage},
+    wrap,
+};
+
+use ansi_term::{Color, Style};
+use chrono::{DateTime, Utc};
+use irc::client::prelude::*;
+use irc::proto::message::Tag;
+use serde::{Serialize, Serializer};
+use std::{convert::TryFrom, fmt::Display};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TwitchEvent {
+    Message(TwitchMessage),
+    UserNotice(UserNotice),
+    Moderation(Moderation),
+    /// Chat settings of the channel changed.
+    RoomState(RoomState),
+    Notice(Notice),
+}
+
+impl TwitchEvent {
+    pub fn channel(&self) -> &str {
+        match self {
+            Self::Message(msg) => msg.channel(),
+            Self::UserNotice(notice) => &notice.channel,
+            Self::Moderation(moderation) => &moderation.channel,
+            Self::RoomState(state) => &state.channel,
+            Self::Notice(notice) => &notice.channel,
+        }
+    }
+
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Message(msg) => msg.timestamp(),
+            Self::UserNotice(notice) => notice.timestamp,
+            Self::Moderation(moderation) => moderation.timestamp,
+            Self::RoomState(_) | Self::Notice(_) => None,
+        }
+    }
+
+    /// Returns the chat message carried by the event, if any.
+    pub fn message(&self) -> Option<&TwitchMessage> {
+        match self {
+            Self::Message(msg) => Some(msg),
+            Self::UserNotice(notice) => notice.message.as_ref(),
+            Self::Moderation(_) | Self::RoomState(_) | Self::Notice(_) => None,
+        }
+    }
+
+    pub fn message_mut(&mut self) -> Option<&mut TwitchMessage> {
+        match self {
+            Self::Message(msg) => Some(msg),
+            Self::UserNotice(notice) => notice.message.as_mut(),
+            Self::Moderation(_) | Self::RoomState(_) | Self::Notice(_) => None,
+        }
+    }
+
+    pub fn display<'a>(&'a self, options: &'a RenderOptions) -> EventDisplay<'a> {
+        EventDisplay {
+            event: self,
+            options,
+        }
+    }
+}
+
+impl TryFrom<irc::proto::Message> for TwitchEvent {
+    type Error = ParseError;
+
+    fn try_from(msg: irc::proto::Message) -> Result<Self, Self::Error> {
+        match &msg.command {
+            Command::PRIVMSG(_, _) => TwitchMessage::try_from(msg).map(Self::Message),
+            Command::Raw(command, _) if command == "USERNOTICE" => {
+                UserNotice::try_from(msg).map(Self::UserNotice)
+            }
+            Command::Raw(command, _) if command == "CLEARCHAT" || command == "CLEARMSG" => {
+                Moderation::try_from(msg).map(Self::Moderation)
+            }
+            Command::NOTICE(_, _) => Notice::try_from(msg).map(Self::Notice),
+            _ => Err(ParseError::InvalidValue("unsupported command")),
+        }
+    }
+}
+
+impl Display for TwitchEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(&RenderOptions::default()).fmt(f)
+    }
+}
+
+pub struct EventDisplay<'a> {
+    event: &'a TwitchEvent,
+    options: &'a RenderOptions,
+}
+
+impl Display for EventDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.event {
+            TwitchEvent::Message(msg) => msg.display(self.options).fmt(f),
+            TwitchEvent::UserNotice(notice) => {
+                self.write_line(
+                    f,
+                    Style::new().fg(Color::Purple).bold(),
+                    &notice.system_message,
+                )?;
+                if let Some(msg) = &notice.message {
+                    write!(f, "\n{}", msg.display(self.options))?;
+                }
+                Ok(())
+            }
+            TwitchEvent::Moderation(moderation) => self.write_line(
+                f,
+                Style::new().fg(Color::Fixed(244)).italic(),
+                &moderation.to_string(),
+            ),
+            TwitchEvent::Notice(notice) => self.write_line(
+                f,
+                Style::new().fg(Color::Fixed(244)).italic(),
+                &notice.message,
+            ),
+            TwitchEvent::RoomState(state) => {
+                self.write_line(f, Style::new().fg(Color::Cyan), &state.to_string())
+            }
+        }
+    }
+}
+
+impl EventDisplay<'_> {
+    /// Writes `text` after the time of the event, if enabled.
+    fn write_line(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        style: Style,
+        text: &str,
+    ) -> std::fmt::Result {
+        let time = time_label(self.options, self.event.timestamp());
+        let mut spans = Vec::new();
+        if let Some((time_style, time)) = &time {
+            spans.push((self.options.color.apply(*time_style), time.as_str()));
+        }
+        spans.push((self.options.color.apply(style), text));
+
+        if let Some(width) = self.options.width {
+            let indent = time.as_ref().map_or(0, |(_, time)| time.len());
+            wrap::write_wrapped(f, &spans, width, indent)
+        } else {
+            for (style, text) in spans {
+                write!(f, "{}", style.paint(text))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UserNoticeKind {
+    Sub,
+    Resub,
+    SubGift,
+    SubMysteryGift,
+    Raid,
+    Other(String),
+}
+
+impl UserNoticeKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Sub => "sub",
+            Self::Resub => "resub",
+            Self::SubGift => "subgift",
+            Self::SubMysteryGift => "submysterygift",
+            Self::Raid => "raid",
+            Self::Other(msg_id) => msg_id,
+        }
+    }
+}
+
+impl Serialize for UserNoticeKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl From<&str> for UserNoticeKind {
+    fn from(msg_id: &str) -> Self {
+        match msg_id {
+            "sub" => Self::Sub,
+            "resub" => Self::Resub,
+            "subgift" => Self::SubGift,
+            "submysterygift" => Self::SubMysteryGift,
+            "raid" => Self::Raid,
+            _ => Self::Other(msg_id.to_owned()),
+        }
+    }
+}
+
+/// Subscriptions, raids and other announcements sent as USERNOTICE.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct UserNotice {
+    channel: String,
+    timestamp: Option<DateTime<Utc>>,
+    kind: UserNoticeKind,
+    system_message: String,
+    message: Option<TwitchMessage>,
+}
+
+impl UserNotice {
+    pub fn kind(&self) -> &UserNoticeKind {
+        &self.kind
+    }
+
+    pub fn system_message(&self) -> &str {
+        &self.system_message
+    }
+
+    pub fn message(&self) -> Option<&TwitchMessage> {
+        self.message.as_ref()
+    }
+
+    /// Whether the notice represents a single new or renewed subscription.
+    ///
+    /// Mystery gifts are followed by one `subgift` notice per recipient, so they
+    /// are not counted.
+    pub fn is_sub(&self) -> bool {
+        matches!(
+            self.kind,
+            UserNoticeKind::Sub | UserNoticeKind::Resub | UserNoticeKind::SubGift
+        )
+    }
+}
+
+impl TryFrom<irc::proto::Message> for UserNotice {
+    type Error = ParseError;
+
+    fn try_from(msg: irc::proto::Message) -> Result<Self, Self::Error> {
+        let params = match &msg.command {
+            Command::Raw(command, params) if command == "USERNOTICE" => params,
+            _ => return Err(ParseError::InvalidValue("not a USERNOTICE")),
+        };
+        let target = params.first().ok_or(ParseError::MissingValue("channel"))?;
+        let tags = msg.tags.as_ref().ok_or(ParseError::MissingValue("tags"))?;
+        let tag = |key| {
+            tags.iter()
+                .find(|tag| tag.0 == key)
+                .and_then(|tag| tag.1.as_deref())
+                .filter(|value| !value.is_empty())
+        };
+
+        let timestamp = tag("tmi-sent-ts").map(parse_timestamp).transpose()?;
+        let kind = tag("msg-id")
+            .ok_or(ParseError::MissingValue("msg-id"))?
+            .into();
+        let system_message = tag("system-msg")
+            .map(str::to_owned)
+            .ok_or(ParseError::MissingValue("system-msg"))?;
+
+        let message = match params.get(1) {
+            Some(content) if !content.is_empty() => {
+                let login = tag("login").ok_or(ParseError::MissingValue("login"))?;
+                Some(TwitchMessage::from_tags(target, login, content, tags)?)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            channel: target.trim_start_matches('#').to_owned(),
+            timestamp,
+            kind,
+            system_message,
+            message,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// All messages in the channel were cleared.
+    ClearChat,
+    Ban {
+        login: String,
+    },
+    Timeout {
+        login: String,
+        duration_secs: u64,
+    },
+    DeleteMessage {
+        login: String,
+        message_id: String,
+        content: String,
+    },
+}
+
+/// Bans, timeouts and message deletions sent as CLEARCHAT and CLEARMSG.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Moderation {
+    channel: String,
+    timestamp: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    action: ModerationAction,
+}
+
+impl Moderation {
+    pub fn action(&self) -> &ModerationAction {
+        &self.action
+    }
+}
+
+impl Display for Moderation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.action {
+            ModerationAction::ClearChat => f.write_str("Chat was cleared by a moderator"),
+            ModerationAction::Ban { login } => write!(f, "{} has been banned", login),
+            ModerationAction::Timeout {
+                login,
+                duration_secs,
+            } => write!(f, "{} has been timed out for {}s", login, duration_secs),
+            ModerationAction::DeleteMessage { login, content, .. } => {
+                write!(f, "A message from {} was deleted: {}", login, content)
+            }
+        }
+    }
+}
+
+impl TryFrom<irc::proto::Message> for Moderation {
+    type Error = ParseError;
+
+    fn try_from(msg: irc::proto::Message) -> Result<Self, Self::Error> {
+        let (command, params) = match &msg.command {
+            Command::Raw(command, params) => (command.as_str(), params),
+            _ => return Err(ParseError::InvalidValue("not a CLEARCHAT or CLEARMSG")),
+        };
+        let target = params.first().ok_or(ParseError::MissingValue("channel"))?;
+        let tags = msg.tags.as_deref().unwrap_or_default();
+        let tag = |key| {
+            tags.iter()
+                .find(|tag| tag.0 == key)
+                .and_then(|tag| tag.1.as_deref())
+                .filter(|value| !value.is_empty())
+        };
+
+        let action = match (command, params.get(1)) {
+            ("CLEARCHAT", None) => ModerationAction::ClearChat,
+            ("CLEARCHAT", Some(login)) => match tag("ban-duration") {
+                Some(duration) => ModerationAction::Timeout {
+                    login: login.clone(),
+                    duration_secs: duration
+                        .parse()
+                        .map_err(|_| ParseError::InvalidValue("ban-duration"))?,
+                },
+                None => ModerationAction::Ban {
+                    login: login.clone(),
+                },
+            },
+            ("CLEARMSG", Some(content)) => ModerationAction::DeleteMessage {
+                login: tag("login")
+                    .ok_or(ParseError::MissingValue("login"))?
+                    .to_owned(),
+                message_id: tag("target-msg-id")
+                    .ok_or(ParseError::MissingValue("target-msg-id"))?
+                    .to_owned(),
+                content: content.clone(),
+            },
+            _ => return Err(ParseError::InvalidValue("not a CLEARCHAT or CLEARMSG")),
+        };
+
+        Ok(Self {
+            channel: target.trim_start_matches('#').to_owned(),
+            timestamp: tag("tmi-sent-ts").map(parse_timestamp).transpose()?,
+            action,
+        })
+    }
+}
+
+/// Informational or error message from the server, sent as NOTICE.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notice {
+    channel: String,
+    msg_id: Option<String>,
+    message: String,
+}
+
+impl Notice {
+    /// The channel the notice is about, or `*` for notices about the connection.
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Identifies the kind of notice, e.g. `msg_channel_suspended` or `slow_on`.
+    /// Login failures come without one.
+    pub fn msg_id(&self) -> Option<&str> {
+        self.msg_id.as_deref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl TryFrom<irc::proto::Message> for Notice {
+    type Error = ParseError;
+
+    fn try_from(msg: irc::proto::Message) -> Result<Self, Self::Error> {
+        let (target, message) = match &msg.command {
+            Command::NOTICE(target, message) => (target, message),
+            _ => return Err(ParseError::InvalidValue("not a NOTICE")),
+        };
+        let msg_id = msg
+            .tags
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .find(|tag| tag.0 == "msg-id")
+            .and_then(|tag| tag.1.clone())
+            .filter(|value| !value.is_empty());
+
+        Ok(Self {
+            channel: target.trim_start_matches('#').to_owned(),
+            msg_id,
+            message: message.clone(),
+        })
+    }
+}
+
+/// Chat settings of a channel, maintained from ROOMSTATE messages.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RoomState {
+    pub channel: String,
+    pub room_id: Option<u64>,
+    pub emote_only: bool,
+    /// Minimum follow duration in minutes required to chat, if followers-only mode is on.
+    pub followers_only: Option<u64>,
+    /// Seconds users have to wait between messages. Zero when slow mode is off.
+    pub slow: u64,
+    pub subs_only: bool,
+    pub r9k: bool,
+}
+
+impl RoomState {
+    pub(crate) fn new(channel: &str) -> Self {
+        Self {
+            channel: channel.trim_start_matches('#').to_owned(),
+            ..Self::default()
+        }
+    }
+
+    /// Applies a ROOMSTATE message. Twitch sends every setting when joining a
+    /// channel but only the changed ones afterwards, so missing tags are left as is.
+    pub(crate) fn update(&mut self, tags: &[Tag]) -> Result<(), ParseError> {
+        for tag in tags {
+            let value = match tag.1.as_deref() {
+                Some(value) if !value.is_empty() => value,
+                _ => continue,
+            };
+            let parse_u64 = |key| value.parse().map_err(|_| ParseError::InvalidValue(key));
+
+            match tag.0.as_str() {
+                "room-id" => self.room_id = Some(parse_u64("room-id")?),
+                "emote-only" => self.emote_only = value == "1",
+                "followers-only" => {
+                    self.followers_only = if value == "-1" {
+                        None
+                    } else {
+                        Some(parse_u64("followers-only")?)
+                    }
+                }
+                "slow" => self.slow = parse_u64("slow")?,
+                "subs-only" => self.subs_only = value == "1",
+                "r9k" => self.r9k = value == "1",
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the active modes in the form of `key=value` pairs, e.g. for HTTP headers.
+    pub fn modes(&self) -> Vec<String> {
+        let mut modes = Vec::new();
+        if self.emote_only {
+            modes.push("emote-only".to_owned());
+        }
+        if let Some(minutes) = self.followers_only {
+            modes.push(format!("followers-only={}", minutes));
+        }
+        if self.slow > 0 {
+            modes.push(format!("slow={}", self.slow));
+        }
+        if self.subs_only {
+            modes.push("subs-only".to_owned());
+        }
+        if self.r9k {
+            modes.push("r9k".to_owned());
+        }
+        modes
+    }
+}
+
+impl Display for RoomState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[#{}", self.channel)?;
+        if let Some(room_id) = self.room_id {
+            write!(f, " (room {})", room_id)?;
+        }
+        f.write_str("] ")?;
+
+        let modes = self.modes();
+        if modes.is_empty() {
+            f.write_str("no chat restrictions")
+        } else {
+            f.write_str(&modes.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_room_state() {
+        let mut state = RoomState::new("#bar");
+        let msg = Message::from(
+            "@emote-only=0;followers-only=10;r9k=0;room-id=1337;slow=30;subs-only=0 :tmi.twitch.tv ROOMSTATE #bar",
+        );
+        state.update(msg.tags.as_deref().unwrap()).unwrap();
+        assert_eq!(state.room_id, Some(1337));
+        assert_eq!(state.followers_only, Some(10));
+        assert_eq!(state.slow, 30);
+        assert_eq!(
+            state.to_string(),
+            "[#bar (room 1337)] followers-only=10, slow=30"
+        );
+
+        let msg = Message::from("@followers-only=-1;room-id=1337 :tmi.twitch.tv ROOMSTATE #bar");
+        state.update(msg.tags.as_deref().unwrap()).unwrap();
+        assert_eq!(state.followers_only, None);
+        assert_eq!(state.modes(), ["slow=30"]);
+    }
+
+    #[test]
+    fn keep_backslashes_in_system_message() {
+        // irc-proto already unescapes tag values
+        let event = TwitchEvent::try_from(Message::from(
+            r"@msg-id=raid;system-msg=back\\slash\sok\\;login=foo;user-id=1;tmi-sent-ts=1 :tmi.twitch.tv USERNOTICE #bar",
+        ))
+        .unwrap();
+        match event {
+            TwitchEvent::UserNotice(notice) => {
+                assert_eq!(notice.system_message(), r"back\slash ok\");
+            }
+            _ => panic!("not a user notice: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn parse_notice() {
+        let event = TwitchEvent::try_from(Message::from(
+            "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #bar :This channel has been suspended.",
+        ))
+        .unwrap();
+        match event {
+            TwitchEvent::Notice(notice) => {
+                assert_eq!(notice.channel(), "bar");
+                assert_eq!(notice.msg_id(), Some("msg_channel_suspended"));
+                assert_eq!(notice.message(), "This channel has been suspended.");
+            }
+            _ => panic!("not a notice: {:?}", event),
+        }
+    }
+}
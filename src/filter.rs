@@ -0,0 +1,115 @@
+use crate::event::TwitchEvent;
+
+use serde::{Deserialize, Deserializer};
+use std::{fmt::Display, str::FromStr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("Unknown filter: {0} (expected highlighted, reward, first or cheer)")]
+pub struct FilterParseError(String);
+
+/// Selects events carrying messages of particular kinds.
+///
+/// An empty filter matches every event. Otherwise an event matches if its
+/// message has any of the selected kinds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventFilter {
+    pub highlighted: bool,
+    pub rewards: bool,
+    pub first_messages: bool,
+    pub cheers: bool,
+}
+
+impl EventFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, event: &TwitchEvent) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        match event.message() {
+            Some(msg) => {
+                (self.highlighted && msg.is_highlighted())
+                    || (self.rewards && msg.reward_id().is_some())
+                    || (self.first_messages && msg.is_first_message())
+                    || (self.cheers && msg.bits().is_some())
+            }
+            None => false,
+        }
+    }
+}
+
+impl FromStr for EventFilter {
+    type Err = FilterParseError;
+
+    /// Parses a comma-separated list of kinds, e.g. `highlighted,first`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Self::default();
+        for kind in s.split(',').map(str::trim).filter(|kind| !kind.is_empty()) {
+            match kind {
+                "highlighted" => filter.highlighted = true,
+                "reward" => filter.rewards = true,
+                "first" => filter.first_messages = true,
+                "cheer" => filter.cheers = true,
+                _ => return Err(FilterParseError(kind.to_owned())),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl Display for EventFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kinds = [
+            (self.highlighted, "highlighted"),
+            (self.rewards, "reward"),
+            (self.first_messages, "first"),
+            (self.cheers, "cheer"),
+        ];
+        let kinds: Vec<_> = kinds
+            .iter()
+            .filter(|(selected, _)| *selected)
+            .map(|(_, kind)| *kind)
+            .collect();
+        f.write_str(&kinds.join(","))
+    }
+}
+
+impl<'de> Deserialize<'de> for EventFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn event(s: &str) -> TwitchEvent {
+        TwitchEvent::try_from(irc::proto::Message::from(s)).unwrap()
+    }
+
+    #[test]
+    fn filter_events() {
+        let first = event("@first-msg=1;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :hi");
+        let highlighted = event(
+            "@msg-id=highlighted-message;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :look",
+        );
+        let reward =
+            event("@custom-reward-id=abc;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :song");
+
+        let filter: EventFilter = "".parse().unwrap();
+        assert!(filter.matches(&first) && filter.matches(&highlighted));
+
+        let filter: EventFilter = "highlighted, reward".parse().unwrap();
+        assert!(!filter.matches(&first));
+        assert!(filter.matches(&highlighted));
+        assert!(filter.matches(&reward));
+
+        assert!("first,foo".parse::<EventFilter>().is_err());
+    }
+}
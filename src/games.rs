@@ -0,0 +1,83 @@
+use crate::{helix, Auth};
+
+use ansi_term::Color;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Serialize)]
+struct TopQueryParams {
+    first: usize,
+}
+
+#[derive(Serialize)]
+struct SearchQueryParams<'a> {
+    query: &'a str,
+    first: usize,
+}
+
+#[derive(Serialize)]
+struct NameQueryParams<'a> {
+    name: &'a str,
+}
+
+#[derive(Deserialize)]
+struct GamesResponse {
+    data: Vec<TwitchGame>,
+}
+
+/// A game or other category streams are listed under, e.g. "Just Chatting".
+#[derive(Clone, Debug, Deserialize)]
+pub struct TwitchGame {
+    id: String,
+    name: String,
+}
+
+impl TwitchGame {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for TwitchGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Color::Blue.paint(&self.name))
+    }
+}
+
+/// Lists the categories with the most viewers.
+pub async fn get_top_games(limit: usize, auth: &Auth) -> actix_web::Result<Vec<TwitchGame>> {
+    let response: GamesResponse =
+        helix::get("games/top", &TopQueryParams { first: limit }, auth).await?;
+    Ok(response.data)
+}
+
+pub async fn search_games(
+    query: &str,
+    limit: usize,
+    auth: &Auth,
+) -> actix_web::Result<Vec<TwitchGame>> {
+    let response: GamesResponse = helix::get(
+        "search/categories",
+        &SearchQueryParams {
+            query,
+            first: limit,
+        },
+        auth,
+    )
+    .await?;
+    Ok(response.data)
+}
+
+/// Looks up a category by its exact name, returning `None` if there is no such
+/// category.
+pub async fn get_game(name: &str, auth: &Auth) -> actix_web::Result<Option<TwitchGame>> {
+    if name.trim().is_empty() {
+        return Ok(None);
+    }
+    let response: GamesResponse = helix::get("games", &NameQueryParams { name }, auth).await?;
+    Ok(response.data.into_iter().next())
+}
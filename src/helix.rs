@@ -0,0 +1,233 @@
+use crate::Auth;
+
+use actix_web::{
+    client::{Client, ClientResponse},
+    http::{HeaderValue, StatusCode},
+    ResponseError,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+/// How many times a request rejected with 429 is sent again.
+const MAX_RETRIES: usize = 3;
+
+thread_local! {
+    // awc clients cannot be shared between threads, so each worker keeps its own
+    // connection pool.
+    static CLIENT: Client = Client::new();
+}
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counts of Helix requests made with a token.
+#[derive(Clone, Debug, Default)]
+pub struct HelixMetrics {
+    pub requests: u64,
+    /// Requests that failed to send or were answered with an error status.
+    pub errors: u64,
+    /// Number of requests that took at most the corresponding
+    /// [`LATENCY_BUCKETS`] bound, not cumulative.
+    pub latency_buckets: [u64; 8],
+    /// Total time spent on requests, in seconds.
+    pub latency_sum: f64,
+}
+
+impl HelixMetrics {
+    fn record(&mut self, latency: Duration, is_error: bool) {
+        let secs = latency.as_secs_f64();
+        self.requests += 1;
+        if is_error {
+            self.errors += 1;
+        }
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.latency_buckets[i] += 1;
+        }
+        self.latency_sum += secs;
+    }
+}
+
+/// Error status returned by Helix, responded with 502.
+#[derive(Debug, Error)]
+#[error("Helix request to /{path} failed with {status}")]
+pub(crate) struct HelixError {
+    path: String,
+    status: StatusCode,
+}
+
+impl ResponseError for HelixError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_GATEWAY
+    }
+}
+
+/// Whether `e` is an error status from Helix that is one of `statuses`.
+pub(crate) fn is_status(e: &actix_web::Error, statuses: &[StatusCode]) -> bool {
+    e.as_error::<HelixError>()
+        .map_or(false, |e| statuses.contains(&e.status))
+}
+
+/// State shared by all requests made with a token.
+#[derive(Debug, Default)]
+pub(crate) struct HelixState {
+    rate_limit: RateLimit,
+    pub metrics: HelixMetrics,
+}
+
+/// Helix rate limit bucket of a token, as reported by the `Ratelimit-*` headers
+/// of the latest response.
+#[derive(Debug, Default)]
+struct RateLimit {
+    remaining: Option<u64>,
+    reset: Option<SystemTime>,
+}
+
+impl RateLimit {
+    /// Takes a point from the bucket, or returns how long to wait for it to be
+    /// refilled if it is empty.
+    fn acquire(&mut self, now: SystemTime) -> Option<Duration> {
+        match (self.remaining, self.reset) {
+            (Some(0), Some(reset)) if reset > now => {
+                return reset.duration_since(now).ok();
+            }
+            (Some(0), _) => {
+                // The bucket has been refilled, but we don't know by how much
+                // until the next response.
+                self.remaining = None;
+                self.reset = None;
+            }
+            (Some(remaining), _) => self.remaining = Some(remaining - 1),
+            (None, _) => (),
+        }
+        None
+    }
+
+    fn update<S>(&mut self, response: &ClientResponse<S>) {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        if let Some(remaining) = header("ratelimit-remaining") {
+            self.remaining = Some(remaining);
+        }
+        if let Some(reset) = header("ratelimit-reset") {
+            self.reset = Some(UNIX_EPOCH + Duration::from_secs(reset));
+        }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            self.remaining = Some(0);
+            // Back off for a moment even if we're not told when to retry
+            let now = SystemTime::now();
+            if self.reset.map_or(true, |reset| reset <= now) {
+                self.reset = Some(now + Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+/// Sends an authenticated GET request to the Helix endpoint at `path` and
+/// parses the JSON response.
+///
+/// Requests made with the same [`Auth`] share a rate limit bucket. When it is
+/// empty, requests wait until it is refilled.
+pub(crate) async fn get<Q: Serialize, T: DeserializeOwned>(
+    path: &str,
+    query: &Q,
+    auth: &Auth,
+) -> actix_web::Result<T> {
+    let mut client_id = HeaderValue::from_str(&auth.client_id)?;
+    client_id.set_sensitive(true);
+
+    let mut retries = 0;
+    loop {
+        loop {
+            let delay = auth
+                .helix
+                .lock()
+                .unwrap()
+                .rate_limit
+                .acquire(SystemTime::now());
+            match delay {
+                Some(delay) => actix_web::rt::time::delay_for(delay).await,
+                None => break,
+            }
+        }
+
+        let started_at = Instant::now();
+        let result = CLIENT
+            .with(Client::clone)
+            .get(format!("{}/{}", HELIX_URL, path))
+            .query(query)?
+            .bearer_auth(&auth.oauth_token)
+            .header("client-id", client_id.clone())
+            .send()
+            .await;
+        let mut response = {
+            let mut helix = auth.helix.lock().unwrap();
+            let is_error = result
+                .as_ref()
+                .map_or(true, |response| !response.status().is_success());
+            helix.metrics.record(started_at.elapsed(), is_error);
+            let response = result?;
+            helix.rate_limit.update(&response);
+            response
+        };
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS && retries < MAX_RETRIES {
+            retries += 1;
+            continue;
+        }
+        if !response.status().is_success() {
+            return Err(HelixError {
+                path: path.to_owned(),
+                status: response.status(),
+            }
+            .into());
+        }
+        return Ok(response.json::<T>().await?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acquire_rate_limit() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let mut rate_limit = RateLimit::default();
+        assert_eq!(rate_limit.acquire(now), None);
+
+        rate_limit.remaining = Some(1);
+        rate_limit.reset = Some(now + Duration::from_secs(5));
+        assert_eq!(rate_limit.acquire(now), None);
+        assert_eq!(rate_limit.acquire(now), Some(Duration::from_secs(5)));
+        assert_eq!(
+            rate_limit.acquire(now + Duration::from_secs(2)),
+            Some(Duration::from_secs(3))
+        );
+
+        assert_eq!(rate_limit.acquire(now + Duration::from_secs(5)), None);
+        assert_eq!(rate_limit.remaining, None);
+    }
+
+    #[test]
+    fn match_error_status() {
+        let e = actix_web::Error::from(HelixError {
+            path: "users".to_owned(),
+            status: StatusCode::BAD_REQUEST,
+        });
+        assert!(is_status(&e, &[StatusCode::BAD_REQUEST]));
+        assert!(!is_status(&e, &[StatusCode::UNAUTHORIZED]));
+        assert_eq!(e.as_response_error().status_code(), StatusCode::BAD_GATEWAY);
+        assert!(!is_status(
+            &actix_web::error::ErrorBadRequest("foo"),
+            &[StatusCode::BAD_REQUEST]
+        ));
+    }
+}
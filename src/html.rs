@@ -0,0 +1,260 @@
+//! Renders events and listings as HTML fragments for browsers.
+
+use crate::{
+    event::TwitchEvent,
+    message::{cheer_color, Segment, TwitchMessage},
+    ChannelInfo, ChatBadges, TwitchGame, TwitchStream,
+};
+
+use chrono::Utc;
+use std::fmt::Write;
+
+const EMOTE_URL: &str = "https://static-cdn.jtvnw.net/emoticons/v2";
+
+/// Escapes `s` for use in element content and quoted attribute values.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// Renders an event as a single-line `<div>`.
+pub fn event_html(event: &TwitchEvent, badges: &ChatBadges) -> String {
+    match event {
+        TwitchEvent::Message(msg) => message_html(msg, badges, ""),
+        TwitchEvent::UserNotice(notice) => {
+            let system_message = format!(
+                r#"<div class="event user-notice">{}</div>"#,
+                escape(notice.system_message())
+            );
+            match notice.message() {
+                Some(msg) => system_message + &message_html(msg, badges, "user-notice"),
+                None => system_message,
+            }
+        }
+        TwitchEvent::Moderation(moderation) => format!(
+            r#"<div class="event moderation">{}</div>"#,
+            escape(&moderation.to_string())
+        ),
+        TwitchEvent::RoomState(state) => format!(
+            r#"<div class="event room-state">{}</div>"#,
+            escape(&state.to_string())
+        ),
+        TwitchEvent::Notice(notice) => format!(
+            r#"<div class="event notice">{}</div>"#,
+            escape(notice.message())
+        ),
+    }
+}
+
+fn message_html(msg: &TwitchMessage, badges: &ChatBadges, class: &str) -> String {
+    let mut classes = vec!["msg"];
+    if !class.is_empty() {
+        classes.push(class);
+    }
+    if msg.is_highlighted() {
+        classes.push("highlighted");
+    }
+    let mut html = format!(r#"<div class="{}">"#, classes.join(" "));
+
+    for badge in msg.badges() {
+        let title = escape(badges.title(badge).unwrap_or_else(|| badge.set_id()));
+        match badges.image_url(badge) {
+            Some(url) => write!(
+                html,
+                r#"<img class="badge" src="{}" alt="{}" title="{}">"#,
+                escape(url),
+                title,
+                title
+            ),
+            None => write!(html, r#"<span class="badge">{}</span>"#, title),
+        }
+        .unwrap();
+    }
+    if msg.is_first_message() {
+        html.push_str(r#"<span class="label first">first message</span>"#);
+    }
+    if msg.reward_id().is_some() {
+        html.push_str(r#"<span class="label reward">reward</span>"#);
+    }
+    if let Some(bits) = msg.bits() {
+        write!(
+            html,
+            r#"<span class="label bits" style="color: {}">{} bits</span>"#,
+            cheer_color(bits),
+            bits
+        )
+        .unwrap();
+    }
+
+    let color = msg
+        .color()
+        .as_ref()
+        .map(|color| format!(r#" style="color: {}""#, color))
+        .unwrap_or_default();
+    write!(
+        html,
+        r#"<span class="name"{}>{}</span>"#,
+        color,
+        escape(msg.display_name())
+    )
+    .unwrap();
+    if msg.is_action() {
+        write!(html, r#" <span class="content action"{}>"#, color).unwrap();
+    } else {
+        html.push_str(r#": <span class="content">"#);
+    }
+
+    for segment in msg.segments() {
+        match segment {
+            Segment::Text(text) => html.push_str(&escape(text)),
+            Segment::Emote { id, name } => write!(
+                html,
+                r#"<img class="emote" src="{}/{}/default/dark/1.0" alt="{}" title="{}">"#,
+                EMOTE_URL,
+                escape(id),
+                escape(name),
+                escape(name)
+            )
+            .unwrap(),
+            Segment::Cheer { text, amount } => write!(
+                html,
+                r#"<span class="cheer" style="color: {}">{}</span>"#,
+                cheer_color(amount),
+                escape(text)
+            )
+            .unwrap(),
+            Segment::Mention(text) => {
+                write!(html, r#"<span class="mention">{}</span>"#, escape(text)).unwrap()
+            }
+            Segment::Url(url) => write!(
+                html,
+                r#"<a href="{}" target="_blank" rel="noopener noreferrer">{}</a>"#,
+                escape(url),
+                escape(url)
+            )
+            .unwrap(),
+        }
+    }
+    html.push_str("</span></div>");
+    html
+}
+
+/// Renders the name, status, game and title of a channel.
+pub fn channel_info_html(info: &ChannelInfo) -> String {
+    let mut html = format!(
+        r#"<span class="name">{}</span> <span class="login">/{}</span> "#,
+        escape(info.display_name()),
+        escape(info.login())
+    );
+    match info.live() {
+        Some(live) => {
+            let uptime = (Utc::now() - live.started_at).num_minutes().max(0);
+            write!(
+                html,
+                r#"<span class="live">LIVE</span> for {}h{:02}m ({} viewers)"#,
+                uptime / 60,
+                uptime % 60,
+                live.viewer_count
+            )
+            .unwrap();
+        }
+        None => html.push_str(r#"<span class="offline">offline</span>"#),
+    }
+    if !info.game_name().is_empty() {
+        write!(
+            html,
+            r#" - <span class="game">{}</span>"#,
+            escape(info.game_name())
+        )
+        .unwrap();
+    }
+    let title = info.title().trim();
+    if !title.is_empty() {
+        write!(html, " - {}", escape(title)).unwrap();
+    }
+    html
+}
+
+/// Renders a stream as a list item linking to its chat.
+pub fn stream_html(stream: &TwitchStream) -> String {
+    let mut html = format!(
+        r#"<li><a class="name" href="/{}">{}</a> <span class="login">/{}</span>"#,
+        escape(&stream.user_login),
+        escape(&stream.user_name),
+        escape(&stream.user_login)
+    );
+    if !stream.game_name.is_empty() {
+        write!(
+            html,
+            r#" - <span class="game">{}</span>"#,
+            escape(&stream.game_name)
+        )
+        .unwrap();
+    }
+    if let Some(viewer_count) = stream.viewer_count {
+        write!(html, " ({} viewers)", viewer_count).unwrap();
+    }
+    let title = stream.title.trim();
+    if !title.is_empty() {
+        write!(html, r#"<div class="title">{}</div>"#, escape(title)).unwrap();
+    }
+    html.push_str("</li>");
+    html
+}
+
+/// Renders a category as a list item linking to its streams.
+pub fn game_html(game: &TwitchGame) -> String {
+    format!(
+        r#"<li><a class="game" href="/-/games/{}">{}</a></li>"#,
+        escape(&percent_encode(game.name())),
+        escape(game.name())
+    )
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            write!(encoded, "%{:02X}", b).unwrap();
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn render_message() {
+        let event = TwitchEvent::try_from(irc::proto::Message::from(
+            "@badges=moderator/1;color=#FF0000;display-name=Foo;emotes=25:0-4;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :Kappa <b>hi</b> https://example.com",
+        ))
+        .unwrap();
+        assert_eq!(
+            event_html(&event, &ChatBadges::default()),
+            concat!(
+                r#"<div class="msg"><span class="badge">moderator</span>"#,
+                r##"<span class="name" style="color: #FF0000">Foo</span>: "##,
+                r#"<span class="content"><img class="emote" src="https://static-cdn.jtvnw.net/emoticons/v2/25/default/dark/1.0" alt="Kappa" title="Kappa">"#,
+                r#" &lt;b&gt;hi&lt;/b&gt; "#,
+                r#"<a href="https://example.com" target="_blank" rel="noopener noreferrer">https://example.com</a>"#,
+                "</span></div>"
+            )
+        );
+        assert_eq!(percent_encode("Just Chatting"), "Just%20Chatting");
+    }
+}
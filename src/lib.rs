@@ -1,33 +1,72 @@
+#[cfg(feature = "archive")]
+pub mod archive;
+mod badges;
+mod channel_info;
 mod channel_stream;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "config")]
+pub mod credentials;
+pub mod event;
+pub mod filter;
+mod games;
+mod helix;
+pub mod html;
 pub mod message;
+pub mod oauth;
+mod recent_messages;
 mod search;
+pub mod stats;
 mod streams;
+mod users;
+pub mod vod;
+mod wrap;
 
-pub use channel_stream::TwitchChannelStream;
+pub use badges::{get_chat_badges, ChatBadges};
+pub use channel_info::{get_channel_info, watch_channel_info, ChannelInfo, LiveStatus};
+pub use channel_stream::{ChannelError, TwitchChannelStream};
+pub use games::{get_game, get_top_games, search_games, TwitchGame};
+pub use helix::{HelixMetrics, LATENCY_BUCKETS};
+pub use recent_messages::get_recent_messages;
 pub use search::search_channels;
-pub use streams::get_streams;
+pub use streams::{get_followed_streams, get_game_streams, get_streams};
+pub use users::{get_current_user, get_user, TwitchUser};
 
 use ansi_term::Color;
 use serde::Deserialize;
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
 
 #[derive(Clone)]
 pub struct Auth {
     client_id: String,
     oauth_token: String,
+    helix: Arc<Mutex<helix::HelixState>>,
 }
 
 impl Auth {
+    pub fn new(client_id: &str, oauth_token: &str) -> Self {
+        Self {
+            client_id: client_id.to_owned(),
+            oauth_token: oauth_token
+                .strip_prefix("oauth:")
+                .unwrap_or(oauth_token)
+                .to_owned(),
+            helix: Default::default(),
+        }
+    }
+
+    /// Returns counts of the Helix requests made with this token so far.
+    pub fn helix_metrics(&self) -> HelixMetrics {
+        self.helix.lock().unwrap().metrics.clone()
+    }
+
     pub fn from_env() -> Result<Self, std::env::VarError> {
         let client_id = std::env::var("CLIENT_ID")?;
-
         let oauth_token = std::env::var("OAUTH_TOKEN")?;
-        let oauth_token = oauth_token.strip_prefix("oauth:").unwrap_or(&oauth_token);
-
-        Ok(Self {
-            client_id,
-            oauth_token: oauth_token.to_owned(),
-        })
+        Ok(Self::new(&client_id, &oauth_token))
     }
 }
 
@@ -1,17 +1,18 @@
+use crate::wrap;
+
 use ansi_term::{Color, Style};
+use chrono::{DateTime, TimeZone, Utc};
 use irc::client::prelude::*;
+use irc::proto::message::Tag;
 use itertools::Itertools;
 use rand::{
     distributions::{Distribution, Standard},
     Rng,
 };
-use std::{
-    convert::TryFrom,
-    fmt::{Display, Write},
-    ops::Range,
-    str::FromStr,
-};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashMap, convert::TryFrom, fmt::Display, ops::Range, str::FromStr};
 use thiserror::Error;
+use unicode_width::UnicodeWidthStr;
 
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -21,22 +22,51 @@ pub enum ParseError {
     InvalidValue(&'static str),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize)]
 pub struct TwitchMessage {
-    user_id: u64,
-    nick_name: String,
-    content: String,
-    display_name: Option<String>,
-    color: Option<TwitchColor>,
-    is_action: bool,
-    emotes: Vec<Emote>,
+    pub(crate) id: Option<String>,
+    pub(crate) channel: String,
+    pub(crate) timestamp: Option<DateTime<Utc>>,
+    pub(crate) user_id: u64,
+    pub(crate) nick_name: String,
+    pub(crate) content: String,
+    pub(crate) display_name: Option<String>,
+    pub(crate) color: Option<TwitchColor>,
+    pub(crate) is_action: bool,
+    pub(crate) emotes: Vec<Emote>,
+    pub(crate) badges: Vec<Badge>,
+    pub(crate) bits: Option<u64>,
+    pub(crate) is_highlighted: bool,
+    pub(crate) reward_id: Option<String>,
+    pub(crate) is_first_message: bool,
 }
 
 impl TwitchMessage {
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Time the message was sent, as reported by Twitch.
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.timestamp
+    }
+
     pub fn user_id(&self) -> u64 {
         self.user_id
     }
 
+    pub fn nick_name(&self) -> &str {
+        &self.nick_name
+    }
+
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.nick_name)
+    }
+
     pub fn color(&self) -> &Option<TwitchColor> {
         &self.color
     }
@@ -44,142 +74,674 @@ impl TwitchMessage {
     pub fn set_color(&mut self, color: TwitchColor) {
         self.color = Some(color);
     }
+
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    pub fn is_action(&self) -> bool {
+        self.is_action
+    }
+
+    pub fn emotes(&self) -> &[Emote] {
+        &self.emotes
+    }
+
+    /// Badges shown next to the user's name, in display order.
+    pub fn badges(&self) -> &[Badge] {
+        &self.badges
+    }
+
+    /// Total amount of bits cheered with the message, if it is a cheer.
+    pub fn bits(&self) -> Option<u64> {
+        self.bits
+    }
+
+    /// Whether the message was highlighted with channel points.
+    pub fn is_highlighted(&self) -> bool {
+        self.is_highlighted
+    }
+
+    /// Id of the custom channel points reward redeemed with the message.
+    pub fn reward_id(&self) -> Option<&str> {
+        self.reward_id.as_deref()
+    }
+
+    /// Whether this is the first message of the user in the channel.
+    pub fn is_first_message(&self) -> bool {
+        self.is_first_message
+    }
+
+    /// Splits the content into text, emotes, cheermotes, mentions and URLs.
+    pub fn segments(&self) -> Vec<Segment<'_>> {
+        let ranges = self
+            .emotes
+            .iter()
+            .flat_map(|emote| emote.ranges.iter().map(move |range| (&emote.id, range)))
+            .sorted_by_key(|(_, range)| range.start);
+
+        let is_cheer = self.bits.is_some();
+        let mut segments = Vec::new();
+        let mut prev_end = 0;
+        for (id, range) in ranges {
+            push_text_segments(
+                &mut segments,
+                &self.content[prev_end..range.start],
+                is_cheer,
+            );
+            segments.push(Segment::Emote {
+                id,
+                name: &self.content[range.clone()],
+            });
+            prev_end = range.end;
+        }
+        push_text_segments(&mut segments, &self.content[prev_end..], is_cheer);
+
+        segments
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Emote {
+        id: &'a str,
+        name: &'a str,
+    },
+    /// A cheermote such as `Cheer100`, giving away `amount` bits.
+    Cheer {
+        text: &'a str,
+        amount: u64,
+    },
+    Mention(&'a str),
+    Url(&'a str),
+}
+
+impl<'a> Segment<'a> {
+    pub fn as_str(&self) -> &'a str {
+        match self {
+            Self::Text(text) | Self::Mention(text) | Self::Url(text) => text,
+            Self::Emote { name, .. } => name,
+            Self::Cheer { text, .. } => text,
+        }
+    }
+}
+
+/// Splits `text` into mentions, URLs, and, if the message is a cheer,
+/// cheermotes, with the rest as plain text.
+fn push_text_segments<'a>(segments: &mut Vec<Segment<'a>>, text: &'a str, is_cheer: bool) {
+    let mut text_start = 0;
+    let mut word_start = None;
+    for (i, ch) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')))
+    {
+        if !ch.is_whitespace() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        let start = match word_start.take() {
+            Some(start) => start,
+            None => continue,
+        };
+
+        let word = &text[start..i];
+        let segment = if let Some(amount) = cheer_amount(word).filter(|_| is_cheer) {
+            Segment::Cheer { text: word, amount }
+        } else if let Some(len) = mention_len(word) {
+            Segment::Mention(&word[..len])
+        } else if let Some(len) = url_len(word) {
+            Segment::Url(&word[..len])
+        } else {
+            continue;
+        };
+
+        if text_start < start {
+            segments.push(Segment::Text(&text[text_start..start]));
+        }
+        text_start = start + segment.as_str().len();
+        segments.push(segment);
+    }
+    if text_start < text.len() {
+        segments.push(Segment::Text(&text[text_start..]));
+    }
+}
+
+/// Prefixes of the global cheermotes. Channels can add their own, which are
+/// not recognized.
+const CHEERMOTE_PREFIXES: &[&str] = &[
+    "4Head",
+    "Anon",
+    "bday",
+    "BibleThump",
+    "Cheer",
+    "cheerwhal",
+    "Corgo",
+    "DansGame",
+    "DoodleCheer",
+    "EleGiggle",
+    "FailFish",
+    "FrankerZ",
+    "HeyGuys",
+    "Kappa",
+    "Kreygasm",
+    "MrDestructoid",
+    "NotLikeThis",
+    "Party",
+    "PJSalt",
+    "Pride",
+    "RIPCheer",
+    "Scoops",
+    "SeemsGood",
+    "Shamrock",
+    "ShowLove",
+    "SwiftRage",
+    "TriHard",
+    "uni",
+    "VoHiYo",
+];
+
+/// Parses the amount of a cheermote, i.e. a known prefix directly followed by a number.
+fn cheer_amount(word: &str) -> Option<u64> {
+    let prefix = word.trim_end_matches(|c: char| c.is_ascii_digit());
+    if !CHEERMOTE_PREFIXES
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(prefix))
+    {
+        return None;
+    }
+    word[prefix.len()..]
+        .parse()
+        .ok()
+        .filter(|&amount| amount > 0)
+}
+
+/// Color of the cheermote tier `bits` falls into.
+pub fn cheer_color(bits: u64) -> TwitchColor {
+    match bits {
+        0..=99 => TwitchColor(151, 151, 151),
+        100..=999 => TwitchColor(156, 62, 232),
+        1000..=4999 => TwitchColor(29, 178, 165),
+        5000..=9999 => TwitchColor(0, 153, 254),
+        _ => TwitchColor(244, 48, 33),
+    }
+}
+
+fn mention_len(word: &str) -> Option<usize> {
+    let name = word.strip_prefix('@')?;
+    let len = name
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(name.len());
+    if len > 0 {
+        Some(len + 1)
+    } else {
+        None
+    }
+}
+
+fn url_len(word: &str) -> Option<usize> {
+    let rest = word
+        .strip_prefix("https://")
+        .or_else(|| word.strip_prefix("http://"))?;
+    let url = word.trim_end_matches(&['.', ',', '!', '?', ')', '\'', '"'][..]);
+    if url.len() > word.len() - rest.len() {
+        Some(url.len())
+    } else {
+        None
+    }
 }
 
 impl TryFrom<irc::proto::Message> for TwitchMessage {
     type Error = ParseError;
 
     fn try_from(msg: irc::proto::Message) -> Result<Self, Self::Error> {
-        if let Command::PRIVMSG(_, content) = &msg.command {
+        if let Command::PRIVMSG(target, content) = &msg.command {
             let nick_name = msg
                 .source_nickname()
                 .ok_or(ParseError::MissingValue("nick name"))?;
+            let tags = msg.tags.as_ref().ok_or(ParseError::MissingValue("tags"))?;
+            Self::from_tags(target, nick_name, content, tags)
+        } else {
+            Err(ParseError::InvalidValue("not a PRIVMSG"))
+        }
+    }
+}
 
-            let mut user_id = None;
-            let mut display_name = None;
-            let mut color = None;
-            let mut is_action = false;
-            let mut emotes = Vec::new();
-
-            let content = if let Some(stripped) = content.strip_prefix("\u{1}ACTION ") {
-                is_action = true;
-                stripped.strip_suffix('\u{1}').unwrap_or(stripped)
-            } else {
-                content
-            };
+impl TwitchMessage {
+    pub(crate) fn from_tags(
+        target: &str,
+        nick_name: &str,
+        content: &str,
+        tags: &[Tag],
+    ) -> Result<Self, ParseError> {
+        let mut id = None;
+        let mut timestamp = None;
+        let mut user_id = None;
+        let mut display_name = None;
+        let mut color = None;
+        let mut is_action = false;
+        let mut emotes = Vec::new();
+        let mut badges = Vec::new();
+        let mut bits = None;
+        let mut is_highlighted = false;
+        let mut reward_id = None;
+        let mut is_first_message = false;
 
-            let tags = msg.tags.as_ref().ok_or(ParseError::MissingValue("tags"))?;
-            for tag in tags {
-                if let Some(value) = &tag.1 {
-                    if value.is_empty() {
-                        continue;
-                    }
+        let content = if let Some(stripped) = content.strip_prefix("\u{1}ACTION ") {
+            is_action = true;
+            stripped.strip_suffix('\u{1}').unwrap_or(stripped)
+        } else {
+            content
+        };
 
-                    match tag.0.as_str() {
-                        "user-id" => {
-                            user_id = Some(
-                                value
-                                    .parse()
-                                    .map_err(|_| ParseError::InvalidValue("user-id"))?,
-                            )
-                        }
-                        "display-name" => display_name = Some(value.clone()),
-                        "color" => {
-                            color = Some(
-                                value
-                                    .parse()
-                                    .map_err(|_| ParseError::InvalidValue("color"))?,
-                            )
-                        }
-                        "emotes" => {
-                            emotes = value
-                                .split('/')
-                                .map(|x| x.parse::<Emote>())
-                                .collect::<Result<Vec<_>, _>>()
-                                .map_err(|_| ParseError::InvalidValue("emotes"))?;
-                        }
-                        _ => (),
+        for tag in tags {
+            if let Some(value) = &tag.1 {
+                if value.is_empty() {
+                    continue;
+                }
+
+                match tag.0.as_str() {
+                    "id" => id = Some(value.clone()),
+                    "tmi-sent-ts" => timestamp = Some(parse_timestamp(value)?),
+                    "user-id" => {
+                        user_id = Some(
+                            value
+                                .parse()
+                                .map_err(|_| ParseError::InvalidValue("user-id"))?,
+                        )
+                    }
+                    "display-name" => display_name = Some(value.clone()),
+                    "color" => {
+                        color = Some(
+                            value
+                                .parse()
+                                .map_err(|_| ParseError::InvalidValue("color"))?,
+                        )
+                    }
+                    "emotes" => {
+                        emotes = value
+                            .split('/')
+                            .map(|x| x.parse::<EmoteTag>())
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|_| ParseError::InvalidValue("emotes"))?;
                     }
+                    "badges" => {
+                        badges = value
+                            .split(',')
+                            .map(|x| x.parse::<Badge>())
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|_| ParseError::InvalidValue("badges"))?;
+                    }
+                    "bits" => {
+                        bits = Some(
+                            value
+                                .parse()
+                                .map_err(|_| ParseError::InvalidValue("bits"))?,
+                        )
+                    }
+                    "msg-id" => is_highlighted = value == "highlighted-message",
+                    "custom-reward-id" => reward_id = Some(value.clone()),
+                    "first-msg" => is_first_message = value == "1",
+                    _ => (),
                 }
             }
-
-            Ok(Self {
-                user_id: user_id.ok_or(ParseError::MissingValue("user-id"))?,
-                nick_name: nick_name.to_owned(),
-                content: content.to_owned(),
-                display_name,
-                color,
-                is_action,
-                emotes,
-            })
-        } else {
-            Err(ParseError::InvalidValue("not a PRIVMSG"))
         }
+
+        Ok(Self {
+            id,
+            channel: target.trim_start_matches('#').to_owned(),
+            timestamp,
+            user_id: user_id.ok_or(ParseError::MissingValue("user-id"))?,
+            nick_name: nick_name.to_owned(),
+            content: content.to_owned(),
+            display_name,
+            color,
+            is_action,
+            emotes: resolve_emotes(content, emotes),
+            badges,
+            bits,
+            is_highlighted,
+            reward_id,
+            is_first_message,
+        })
     }
+
+    pub fn display<'a>(&'a self, options: &'a RenderOptions) -> MessageDisplay<'a> {
+        MessageDisplay { msg: self, options }
+    }
+}
+
+/// Parses a `tmi-sent-ts` tag, which is in milliseconds since the Unix epoch.
+pub(crate) fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, ParseError> {
+    value
+        .parse()
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .ok_or(ParseError::InvalidValue("tmi-sent-ts"))
 }
 
 impl Display for TwitchMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let name = match &self.display_name {
-            Some(display) if display.eq_ignore_ascii_case(&self.nick_name) => display.clone(),
-            Some(display) => format!("{} ({})", display, self.nick_name),
-            None => self.nick_name.clone(),
+        self.display(&RenderOptions::default()).fmt(f)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// Wraps lines to this many columns, indenting continuation lines under the message body.
+    pub width: Option<usize>,
+    pub color: ColorMode,
+    /// Words to emphasize in message contents, matched case-insensitively.
+    pub highlights: Vec<String>,
+    /// Prefixes events with the time they were sent, in UTC.
+    pub timestamps: bool,
+    /// Shows the badges of users, e.g. `[moderator]`, before their names.
+    pub badges: bool,
+}
+
+/// Label showing when an event was sent, if enabled and known.
+pub(crate) fn time_label(
+    options: &RenderOptions,
+    timestamp: Option<DateTime<Utc>>,
+) -> Option<(Style, String)> {
+    if !options.timestamps {
+        return None;
+    }
+    timestamp.map(|timestamp| {
+        (
+            Color::Fixed(244).normal(),
+            timestamp.format("%H:%M:%S ").to_string(),
+        )
+    })
+}
+
+/// Colors the terminal supports. Colors are downgraded to the closest supported ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorMode {
+    /// No escape sequences at all, not even for bold or underline.
+    None,
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl Default for ColorMode {
+    fn default() -> Self {
+        Self::TrueColor
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "16" => Ok(Self::Ansi16),
+            "256" => Ok(Self::Ansi256),
+            "truecolor" => Ok(Self::TrueColor),
+            _ => Err(ParseError::InvalidValue("color mode")),
+        }
+    }
+}
+
+impl Display for ColorMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Ansi16 => "16",
+            Self::Ansi256 => "256",
+            Self::TrueColor => "truecolor",
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ColorMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl ColorMode {
+    /// Converts `style` into one the terminal can display.
+    pub fn apply(self, style: Style) -> Style {
+        let convert = |color: Option<Color>| -> Option<Color> {
+            match (self, color?) {
+                (Self::TrueColor, color) => Some(color),
+                (Self::Ansi256, Color::RGB(r, g, b)) => Some(Color::Fixed(rgb_to_256(r, g, b))),
+                (Self::Ansi256, color) => Some(color),
+                (Self::Ansi16, Color::RGB(r, g, b)) => Some(rgb_to_16(r, g, b)),
+                (Self::Ansi16, Color::Fixed(n)) => {
+                    let (r, g, b) = fixed_to_rgb(n);
+                    Some(rgb_to_16(r, g, b))
+                }
+                (Self::Ansi16, color) => Some(color),
+                (Self::None, _) => None,
+            }
         };
+        match self {
+            Self::None => Style::new(),
+            _ => Style {
+                foreground: convert(style.foreground),
+                background: convert(style.background),
+                ..style
+            },
+        }
+    }
+}
 
-        if let Some(color) = self.color {
-            write!(f, "{}", Color::from(color).paint(name))?;
-        } else {
-            f.write_str(&name)?;
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        // grayscale ramp from 8 to 238 in steps of 10
+        return match r {
+            0..=3 => 16,
+            244..=255 => 231,
+            _ => 232 + ((r.saturating_sub(3)) / 10).min(23),
+        };
+    }
+    let to_6 = |x: u8| ((x as u16 * 5 + 127) / 255) as u8;
+    16 + 36 * to_6(r) + 6 * to_6(g) + to_6(b)
+}
+
+fn fixed_to_rgb(n: u8) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (128, 0, 0),
+        (0, 128, 0),
+        (128, 128, 0),
+        (0, 0, 128),
+        (128, 0, 128),
+        (0, 128, 128),
+        (192, 192, 192),
+        (128, 128, 128),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (0, 0, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    match n {
+        0..=15 => BASIC[n as usize],
+        16..=231 => {
+            let level = |x: u8| if x == 0 { 0 } else { 55 + x * 40 };
+            let n = n - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
         }
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+fn rgb_to_16(r: u8, g: u8, b: u8) -> Color {
+    const COLORS: [(Color, (u8, u8, u8)); 8] = [
+        (Color::Black, (0, 0, 0)),
+        (Color::Red, (205, 0, 0)),
+        (Color::Green, (0, 205, 0)),
+        (Color::Yellow, (205, 205, 0)),
+        (Color::Blue, (0, 0, 238)),
+        (Color::Purple, (205, 0, 205)),
+        (Color::Cyan, (0, 205, 205)),
+        (Color::White, (229, 229, 229)),
+    ];
+    let distance = |(cr, cg, cb): (u8, u8, u8)| {
+        let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2);
+        d(r, cr) + d(g, cg) + d(b, cb)
+    };
+    COLORS
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb))
+        .map(|(color, _)| *color)
+        .unwrap()
+}
+
+/// Splits `text` into parts, marking the ones that match any of `highlights`.
+fn split_highlights<'a>(text: &'a str, highlights: &[String]) -> Vec<(bool, &'a str)> {
+    // ASCII lowercasing keeps byte offsets intact.
+    let lowercase = text.to_ascii_lowercase();
+    let mut parts = Vec::new();
+    let mut pos = 0;
+    while pos < text.len() {
+        let next = highlights
+            .iter()
+            .filter(|word| !word.is_empty())
+            .filter_map(|word| {
+                let start = pos + lowercase[pos..].find(&word.to_ascii_lowercase())?;
+                Some(start..start + word.len())
+            })
+            .min_by_key(|range| (range.start, std::cmp::Reverse(range.end)));
+        match next {
+            Some(range) if text.is_char_boundary(range.end) => {
+                if pos < range.start {
+                    parts.push((false, &text[pos..range.start]));
+                }
+                parts.push((true, &text[range.clone()]));
+                pos = range.end;
+            }
+            _ => break,
+        }
+    }
+    if pos < text.len() {
+        parts.push((false, &text[pos..]));
+    }
+    parts
+}
+
+pub struct MessageDisplay<'a> {
+    msg: &'a TwitchMessage,
+    options: &'a RenderOptions,
+}
+
+impl Display for MessageDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = self.msg;
+
+        let name = match &msg.display_name {
+            Some(display) if display.eq_ignore_ascii_case(&msg.nick_name) => display.clone(),
+            Some(display) => format!("{} ({})", display, msg.nick_name),
+            None => msg.nick_name.clone(),
+        };
+        let name_style = msg
+            .color
+            .map(|color| Color::from(color).normal())
+            .unwrap_or_default();
 
         let mut style = Style::new();
-        if self.is_action {
+        let separator = if msg.is_action {
             style = style.italic();
-            f.write_char(' ')?;
+            " "
         } else {
-            f.write_str(": ")?;
+            ": "
+        };
+        if msg.is_highlighted {
+            style = style.on(Color::Fixed(54));
         }
 
-        let ranges = self
-            .emotes
+        let mut labels: Vec<_> = time_label(self.options, msg.timestamp)
+            .into_iter()
+            .collect();
+        if self.options.badges {
+            for badge in &msg.badges {
+                labels.push((Style::new().bold(), format!("[{}] ", badge.set_id)));
+            }
+        }
+        if msg.is_first_message {
+            labels.push((Color::Green.bold(), "[first message] ".to_owned()));
+        }
+        if msg.reward_id.is_some() {
+            labels.push((Color::Cyan.bold(), "[reward] ".to_owned()));
+        }
+        if let Some(bits) = msg.bits {
+            let bits_style = Color::from(cheer_color(bits)).bold();
+            labels.push((bits_style, format!("[{} bits] ", bits)));
+        }
+
+        let mut spans: Vec<_> = labels
             .iter()
-            .flat_map(|emote| &emote.ranges)
-            .sorted_by_key(|range| range.start);
+            .map(|(style, label)| (*style, label.as_str()))
+            .collect();
+        spans.push((name_style, name.as_str()));
+        spans.push((Style::new(), separator));
+        let indent = labels.iter().map(|(_, label)| label.len()).sum::<usize>()
+            + name.width()
+            + separator.len();
 
-        let mut prev_end = 0;
-        for range in ranges {
-            if prev_end < range.start {
-                let text: String = self
-                    .content
-                    .chars()
-                    .skip(prev_end)
-                    .take(range.start - prev_end)
-                    .collect();
-                write!(f, "{}", style.paint(text))?;
-            }
-            if range.start < range.end {
-                let text: String = self
-                    .content
-                    .chars()
-                    .skip(range.start)
-                    .take(range.end - range.start)
-                    .collect();
-                write!(f, "{}", style.underline().paint(text))?;
-            }
-            prev_end = range.end;
+        for segment in msg.segments() {
+            let segment_style = match segment {
+                Segment::Text(text) => {
+                    for (is_highlight, part) in split_highlights(text, &self.options.highlights) {
+                        if is_highlight {
+                            spans.push((style.on(Color::Yellow).fg(Color::Black), part));
+                        } else {
+                            spans.push((style, part));
+                        }
+                    }
+                    continue;
+                }
+                Segment::Emote { .. } => style.underline(),
+                Segment::Cheer { amount, .. } => style.fg(cheer_color(amount).into()).bold(),
+                Segment::Mention(_) => style.bold(),
+                Segment::Url(_) => style.fg(Color::Blue).underline(),
+            };
+            spans.push((segment_style, segment.as_str()));
         }
-        if prev_end < self.content.chars().count() {
-            let text: String = self.content.chars().skip(prev_end).collect();
-            write!(f, "{}", style.paint(text))?;
+
+        for span in &mut spans {
+            span.0 = self.options.color.apply(span.0);
         }
 
-        Ok(())
+        if let Some(width) = self.options.width {
+            wrap::write_wrapped(f, &spans, width, indent)
+        } else {
+            for (style, text) in spans {
+                if !text.is_empty() {
+                    write!(f, "{}", style.paint(text))?;
+                }
+            }
+            Ok(())
+        }
     }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct TwitchColor(u8, u8, u8);
 
+impl Display for TwitchColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+impl Serialize for TwitchColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl FromStr for TwitchColor {
     type Err = ();
 
@@ -218,6 +780,22 @@ impl FallbackColor {
     const NUM_COLORS: usize = 15;
 }
 
+/// Remembers a random fallback color for each user without a color set.
+#[derive(Default)]
+pub(crate) struct FallbackColorMap(HashMap<u64, FallbackColor>);
+
+impl FallbackColorMap {
+    pub(crate) fn fill(&mut self, msg: &mut TwitchMessage) {
+        if msg.color.is_none() {
+            let color = *self
+                .0
+                .entry(msg.user_id)
+                .or_insert_with(|| rand::thread_rng().gen());
+            msg.color = Some(color.into());
+        }
+    }
+}
+
 impl Distribution<FallbackColor> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> FallbackColor {
         FallbackColor(rng.gen_range(0..FallbackColor::NUM_COLORS as u8))
@@ -247,36 +825,183 @@ impl From<FallbackColor> for TwitchColor {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Emote {
+    pub(crate) id: String,
+    pub(crate) ranges: Vec<Range<usize>>,
+}
+
+impl Emote {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Byte ranges of the occurrences of the emote in the message content.
+    pub fn ranges(&self) -> &[Range<usize>] {
+        &self.ranges
+    }
+}
+
+/// A chat badge such as `subscriber/12`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Badge {
+    pub(crate) set_id: String,
+    pub(crate) version: String,
+}
+
+impl Badge {
+    /// Kind of the badge, e.g. `moderator` or `subscriber`.
+    pub fn set_id(&self) -> &str {
+        &self.set_id
+    }
+
+    /// Variant of the badge, e.g. the number of months for `subscriber`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+}
+
+impl FromStr for Badge {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (set_id, version) = s.split_once('/').ok_or(())?;
+        if set_id.is_empty() {
+            return Err(());
+        }
+        Ok(Self {
+            set_id: set_id.to_owned(),
+            version: version.to_owned(),
+        })
+    }
+}
+
+/// Emote positions as sent in the `emotes` tag, i.e. before they are checked
+/// against the message content.
 #[derive(Debug, PartialEq)]
-struct Emote {
-    id: usize,
+struct EmoteTag {
+    id: String,
     ranges: Vec<Range<usize>>,
 }
 
-impl FromStr for Emote {
+impl FromStr for EmoteTag {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (id_str, indices_str) = s.split_once(':').ok_or(())?;
+        let (id, indices_str) = s.split_once(':').ok_or(())?;
+        if id.is_empty() {
+            return Err(());
+        }
         let ranges = indices_str
             .split(',')
             .map(|range_str| {
                 let (start, end) = range_str.split_once('-').ok_or(())?;
                 let start = start.parse().map_err(|_| ())?;
                 let end: usize = end.parse().map_err(|_| ())?;
-                Ok(start..end + 1)
+                Ok(start..end.saturating_add(1))
             })
             .collect::<Result<Vec<_>, _>>()?;
         Ok(Self {
-            id: id_str.parse().map_err(|_| ())?,
+            id: id.to_owned(),
             ranges,
         })
     }
 }
 
+/// Converts emote positions into byte ranges of `content`, dropping ones that
+/// don't point at a whole word or that overlap a preceding emote.
+///
+/// Twitch counts positions in code points, but some clients and older messages
+/// count UTF-16 code units instead, so both are tried and the interpretation that
+/// yields more valid ranges wins.
+fn resolve_emotes(content: &str, tags: Vec<EmoteTag>) -> Vec<Emote> {
+    let code_points: Vec<_> = content
+        .char_indices()
+        .map(|(i, _)| Some(i))
+        .chain(std::iter::once(Some(content.len())))
+        .collect();
+    let utf16_units: Vec<_> = content
+        .char_indices()
+        .flat_map(|(i, ch)| {
+            std::iter::once(Some(i)).chain(std::iter::repeat(None).take(ch.len_utf16() - 1))
+        })
+        .chain(std::iter::once(Some(content.len())))
+        .collect();
+
+    let to_byte_ranges = |offsets: &[Option<usize>]| -> Vec<Option<Range<usize>>> {
+        tags.iter()
+            .flat_map(|tag| &tag.ranges)
+            .map(|range| {
+                let start = (*offsets.get(range.start)?)?;
+                let end = (*offsets.get(range.end)?)?;
+                if start < end && is_word(content, start..end) {
+                    Some(start..end)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    };
+
+    let mut byte_ranges = to_byte_ranges(&code_points);
+    if byte_ranges.iter().any(Option::is_none) {
+        let utf16_ranges = to_byte_ranges(&utf16_units);
+        if utf16_ranges.iter().flatten().count() > byte_ranges.iter().flatten().count() {
+            byte_ranges = utf16_ranges;
+        }
+    }
+
+    let mut valid: Vec<_> = tags
+        .iter()
+        .flat_map(|tag| tag.ranges.iter().map(move |_| tag.id.as_str()))
+        .zip(byte_ranges)
+        .filter_map(|(id, range)| range.map(|range| (id, range)))
+        .collect();
+    valid.sort_by_key(|(_, range)| range.start);
+    let mut prev_end = 0;
+    valid.retain(|(_, range)| {
+        if range.start < prev_end {
+            return false;
+        }
+        prev_end = range.end;
+        true
+    });
+
+    let mut emotes: Vec<Emote> = Vec::new();
+    for tag in &tags {
+        let ranges: Vec<_> = valid
+            .iter()
+            .filter(|(id, _)| *id == tag.id)
+            .map(|(_, range)| range.clone())
+            .collect();
+        if !ranges.is_empty() && emotes.iter().all(|emote| emote.id != tag.id) {
+            emotes.push(Emote {
+                id: tag.id.clone(),
+                ranges,
+            });
+        }
+    }
+    emotes
+}
+
+fn is_word(s: &str, range: Range<usize>) -> bool {
+    !s[range.clone()].contains(char::is_whitespace)
+        && s[..range.start]
+            .chars()
+            .next_back()
+            .map_or(true, char::is_whitespace)
+        && s[range.end..]
+            .chars()
+            .next()
+            .map_or(true, char::is_whitespace)
+}
+
 #[cfg(test)]
+// Emote ranges are lists, even if they often hold a single range
+#[allow(clippy::single_range_in_vec_init)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn parse_message() {
@@ -286,6 +1011,9 @@ mod tests {
         assert_eq!(
             msg,
             TwitchMessage {
+                id: Some("b34ccfc7-4977-403a-8a94-33c6bac34fb8".to_owned()),
+                channel: "ronni".to_owned(),
+                timestamp: Utc.timestamp_millis_opt(1507246572675).single(),
                 user_id: 1337,
                 nick_name: "ronni".to_owned(),
                 content: "Kappa Keepo Kappa".to_owned(),
@@ -294,14 +1022,28 @@ mod tests {
                 is_action: false,
                 emotes: vec![
                     Emote {
-                        id: 25,
+                        id: "25".to_owned(),
                         ranges: vec![0..5, 12..17]
                     },
                     Emote {
-                        id: 1902,
+                        id: "1902".to_owned(),
                         ranges: vec![6..11,]
                     }
                 ],
+                badges: vec![
+                    Badge {
+                        set_id: "global_mod".to_owned(),
+                        version: "1".to_owned(),
+                    },
+                    Badge {
+                        set_id: "turbo".to_owned(),
+                        version: "1".to_owned(),
+                    },
+                ],
+                bits: None,
+                is_highlighted: false,
+                reward_id: None,
+                is_first_message: false,
             }
         );
 
@@ -317,4 +1059,132 @@ mod tests {
             ),
         );
     }
+
+    fn parse_privmsg(emotes: &str, content: &str) -> TwitchMessage {
+        let msg = format!(
+            "@emotes={};user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #foo :{}",
+            emotes, content
+        );
+        TwitchMessage::try_from(irc::proto::Message::from(msg.as_str())).unwrap()
+    }
+
+    #[test]
+    fn display_timestamps_and_badges() {
+        let msg = TwitchMessage::try_from(irc::proto::Message::from(
+            "@badges=moderator/1,subscriber/12;display-name=Foo;tmi-sent-ts=1507246572675;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :hi",
+        ))
+        .unwrap();
+        let options = RenderOptions {
+            color: ColorMode::None,
+            timestamps: true,
+            badges: true,
+            ..RenderOptions::default()
+        };
+        assert_eq!(
+            msg.display(&options).to_string(),
+            "23:36:12 [moderator] [subscriber] Foo: hi"
+        );
+    }
+
+    #[test]
+    fn parse_emotes_after_emoji() {
+        // code points
+        let msg = parse_privmsg("25:9-13", "👨‍👩‍👧 hi Kappa");
+        assert_eq!(
+            msg.segments(),
+            vec![
+                Segment::Text("👨‍👩‍👧 hi "),
+                Segment::Emote {
+                    id: "25",
+                    name: "Kappa"
+                }
+            ]
+        );
+
+        // UTF-16 code units
+        let msg = parse_privmsg("25:12-16", "👨‍👩‍👧 hi Kappa");
+        assert_eq!(msg.emotes()[0].ranges(), &[22..27]);
+
+        // out of bounds, overlapping and not on word boundaries
+        let msg = parse_privmsg("25:0-4,2-6,40-44/1902:6-8", "Kappa Keepo");
+        assert_eq!(
+            msg.emotes(),
+            &[Emote {
+                id: "25".to_owned(),
+                ranges: vec![0..5]
+            }]
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let msg = parse_privmsg("", "@bar, see https://example.com/a?b=c). @ ok");
+        assert_eq!(
+            msg.segments(),
+            vec![
+                Segment::Mention("@bar"),
+                Segment::Text(", see "),
+                Segment::Url("https://example.com/a?b=c"),
+                Segment::Text("). @ ok"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_cheer() {
+        let msg = TwitchMessage::try_from(irc::proto::Message::from(
+            "@bits=150;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #foo :Cheer100 gg BibleThump50 x2",
+        ))
+        .unwrap();
+        assert_eq!(msg.bits(), Some(150));
+        assert_eq!(
+            msg.segments(),
+            vec![
+                Segment::Cheer {
+                    text: "Cheer100",
+                    amount: 100
+                },
+                Segment::Text(" gg "),
+                Segment::Cheer {
+                    text: "BibleThump50",
+                    amount: 50
+                },
+                Segment::Text(" x2"),
+            ]
+        );
+
+        // not a cheer
+        let msg = parse_privmsg("", "Cheer100");
+        assert_eq!(msg.segments(), vec![Segment::Text("Cheer100")]);
+    }
+
+    proptest! {
+        #[test]
+        fn segments_cover_content(
+            content in "[^\r\n\u{0}]*",
+            ranges in prop::collection::vec((0usize..64, 0usize..64), 0..8),
+        ) {
+            let emotes = ranges
+                .iter()
+                .enumerate()
+                .map(|(id, (start, end))| format!("{}:{}-{}", id, start, end))
+                .join("/");
+            let msg = parse_privmsg(&emotes, &content);
+
+            let segments = msg.segments();
+            prop_assert_eq!(
+                segments.iter().map(Segment::as_str).collect::<String>(),
+                msg.content()
+            );
+
+            let mut ranges: Vec<_> = msg.emotes().iter().flat_map(Emote::ranges).collect();
+            ranges.sort_by_key(|range| range.start);
+            for pair in ranges.windows(2) {
+                prop_assert!(pair[0].end <= pair[1].start);
+            }
+            for range in ranges {
+                prop_assert!(!msg.content()[range.clone()].is_empty());
+            }
+        }
+    }
 }
@@ -0,0 +1,198 @@
+use actix_web::{client::ClientResponse, error, http::StatusCode};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_AUTH_URL: &str = "https://id.twitch.tv/oauth2";
+
+/// Talks to the Twitch OAuth server to obtain, validate and revoke user tokens
+/// with the device code flow.
+pub struct OAuthClient {
+    auth_url: String,
+    client_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    /// Code to enter at `verification_uri`.
+    pub user_code: String,
+    pub verification_uri: String,
+    /// Seconds until the codes expire.
+    pub expires_in: u64,
+    /// Seconds to wait between polls for the token.
+    pub interval: u64,
+    /// Scopes requested with the code, which the token request repeats.
+    #[serde(skip)]
+    scopes: String,
+}
+
+#[derive(Deserialize)]
+pub struct Token {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub scope: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenInfo {
+    pub client_id: String,
+    pub login: Option<String>,
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Seconds until the token expires.
+    pub expires_in: u64,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct DeviceParams<'a> {
+    client_id: &'a str,
+    scopes: &'a str,
+}
+
+#[derive(Serialize)]
+struct TokenParams<'a> {
+    client_id: &'a str,
+    device_code: &'a str,
+    scopes: &'a str,
+    grant_type: &'a str,
+}
+
+#[derive(Serialize)]
+struct RefreshParams<'a> {
+    client_id: &'a str,
+    refresh_token: &'a str,
+    grant_type: &'a str,
+}
+
+#[derive(Serialize)]
+struct RevokeParams<'a> {
+    client_id: &'a str,
+    token: &'a str,
+}
+
+impl OAuthClient {
+    /// `auth_url` is the base URL of the OAuth server, e.g. [`DEFAULT_AUTH_URL`].
+    pub fn new(client_id: &str, auth_url: &str) -> Self {
+        Self {
+            auth_url: auth_url.trim_end_matches('/').to_owned(),
+            client_id: client_id.to_owned(),
+        }
+    }
+
+    /// Starts a login. The user then has to enter the returned user code.
+    pub async fn request_device_code(&self, scopes: &[&str]) -> actix_web::Result<DeviceCode> {
+        let scopes = scopes.join(" ");
+        let mut response = actix_web::client::Client::new()
+            .post(format!("{}/device", self.auth_url))
+            .send_form(&DeviceParams {
+                client_id: &self.client_id,
+                scopes: &scopes,
+            })
+            .await?;
+        check_status(&mut response).await?;
+        let mut code: DeviceCode = response.json().await?;
+        code.scopes = scopes;
+        Ok(code)
+    }
+
+    /// Asks whether the user has entered the code yet, returning `None` if not.
+    /// Lengthens `code.interval` if the server asks to poll less often.
+    pub async fn poll_token(&self, code: &mut DeviceCode) -> actix_web::Result<Option<Token>> {
+        let mut response = actix_web::client::Client::new()
+            .post(format!("{}/token", self.auth_url))
+            .send_form(&TokenParams {
+                client_id: &self.client_id,
+                device_code: &code.device_code,
+                scopes: &code.scopes,
+                grant_type: "urn:ietf:params:oauth:grant-type:device_code",
+            })
+            .await?;
+
+        if response.status() == StatusCode::BAD_REQUEST {
+            let body: ErrorResponse = response.json().await?;
+            return match body.message.as_str() {
+                "authorization_pending" => Ok(None),
+                "slow_down" => {
+                    // RFC 8628 asks for 5 more seconds between polls.
+                    code.interval += 5;
+                    Ok(None)
+                }
+                _ => Err(error::ErrorUnauthorized(body.message)),
+            };
+        }
+        check_status(&mut response).await?;
+        Ok(Some(response.json().await?))
+    }
+
+    /// Gets a new token with the refresh token of an expired one, returning
+    /// `None` if the refresh token is invalid or revoked.
+    pub async fn refresh(&self, refresh_token: &str) -> actix_web::Result<Option<Token>> {
+        let mut response = actix_web::client::Client::new()
+            .post(format!("{}/token", self.auth_url))
+            .send_form(&RefreshParams {
+                client_id: &self.client_id,
+                refresh_token,
+                grant_type: "refresh_token",
+            })
+            .await?;
+
+        if matches!(
+            response.status(),
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED
+        ) {
+            return Ok(None);
+        }
+        check_status(&mut response).await?;
+        Ok(Some(response.json().await?))
+    }
+
+    /// Returns `None` if the token is invalid, expired or revoked.
+    pub async fn validate(&self, access_token: &str) -> actix_web::Result<Option<TokenInfo>> {
+        let mut response = actix_web::client::Client::new()
+            .get(format!("{}/validate", self.auth_url))
+            .header("authorization", format!("OAuth {}", access_token))
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        check_status(&mut response).await?;
+        Ok(Some(response.json().await?))
+    }
+
+    pub async fn revoke(&self, access_token: &str) -> actix_web::Result<()> {
+        let mut response = actix_web::client::Client::new()
+            .post(format!("{}/revoke", self.auth_url))
+            .send_form(&RevokeParams {
+                client_id: &self.client_id,
+                token: access_token,
+            })
+            .await?;
+        check_status(&mut response).await
+    }
+}
+
+async fn check_status<S>(response: &mut ClientResponse<S>) -> actix_web::Result<()>
+where
+    S: Stream<Item = Result<actix_web::web::Bytes, actix_web::error::PayloadError>> + Unpin,
+{
+    if response.status().is_success() {
+        return Ok(());
+    }
+    let message = match response.json::<ErrorResponse>().await {
+        Ok(body) => body.message,
+        Err(_) => response.status().to_string(),
+    };
+    Err(error::ErrorBadGateway(format!(
+        "OAuth request failed: {}",
+        message
+    )))
+}
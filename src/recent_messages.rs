@@ -0,0 +1,85 @@
+use crate::event::TwitchEvent;
+
+use actix_web::{client::Client, error};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Response of a recent-messages service, e.g.
+/// <https://recent-messages.robotty.de/api/v2/recent-messages>.
+#[derive(Deserialize)]
+struct RecentMessages {
+    /// Raw IRC lines, oldest first.
+    messages: Vec<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RecentMessagesParams {
+    limit: usize,
+}
+
+/// Gets up to `limit` events sent to `channel` before joining it from the
+/// recent-messages service at `url`. Lines that cannot be parsed are skipped.
+pub async fn get_recent_messages(
+    url: &str,
+    channel: &str,
+    limit: usize,
+) -> actix_web::Result<Vec<TwitchEvent>> {
+    let mut response = Client::new()
+        .get(format!(
+            "{}/{}",
+            url.trim_end_matches('/'),
+            channel.to_lowercase()
+        ))
+        .query(&RecentMessagesParams { limit })?
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(error::ErrorBadGateway(format!(
+            "Recent messages request failed with {}",
+            response.status()
+        )));
+    }
+    let body: RecentMessages = response.json().limit(1 << 20).await?;
+    if let Some(e) = body.error {
+        return Err(error::ErrorBadGateway(format!(
+            "Recent messages request failed: {}",
+            e
+        )));
+    }
+
+    Ok(parse_lines(&body.messages, limit))
+}
+
+fn parse_lines(lines: &[String], limit: usize) -> Vec<TwitchEvent> {
+    let mut events: Vec<_> = lines
+        .iter()
+        .filter_map(|line| line.parse::<irc::proto::Message>().ok())
+        .filter_map(|msg| TwitchEvent::try_from(msg).ok())
+        .collect();
+    let skip = events.len().saturating_sub(limit);
+    events.drain(..skip);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_recent_messages() {
+        let lines = vec![
+            "@user-id=1;tmi-sent-ts=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :first".to_owned(),
+            ":tmi.twitch.tv 001 justinfan12345 :Welcome".to_owned(),
+            "@user-id=1;tmi-sent-ts=2 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :second".to_owned(),
+            "@user-id=1;tmi-sent-ts=3 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :third".to_owned(),
+        ];
+        let events = parse_lines(&lines, 2);
+        let contents: Vec<_> = events
+            .iter()
+            .filter_map(TwitchEvent::message)
+            .map(|msg| msg.content())
+            .collect();
+        assert_eq!(contents, ["second", "third"]);
+    }
+}
@@ -1,6 +1,5 @@
-use crate::{Auth, TwitchStream};
+use crate::{helix, Auth, TwitchStream};
 
-use actix_web::http::HeaderValue;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
@@ -40,22 +39,16 @@ pub async fn search_channels(
     limit: usize,
     auth: &Auth,
 ) -> actix_web::Result<Vec<TwitchStream>> {
-    let mut client_id = HeaderValue::from_str(&auth.client_id)?;
-    client_id.set_sensitive(true);
-
-    let response = actix_web::client::Client::new()
-        .get("https://api.twitch.tv/helix/search/channels")
-        .query(&QueryParams {
+    let response: ChannelsResponse = helix::get(
+        "search/channels",
+        &QueryParams {
             query,
             first: limit,
             live_only: true,
-        })?
-        .bearer_auth(&auth.oauth_token)
-        .header("client-id", client_id)
-        .send()
-        .await?
-        .json::<ChannelsResponse>()
-        .await?;
+        },
+        auth,
+    )
+    .await?;
 
     let streams = response.data.into_iter().map(Into::into).collect();
     Ok(streams)
@@ -0,0 +1,274 @@
+use crate::event::{TwitchEvent, UserNoticeKind};
+
+use ansi_term::{Color, Style};
+use itertools::Itertools;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Aggregates activity of a channel from the events of a [`crate::TwitchChannelStream`].
+pub struct ChatStats {
+    started_at: Instant,
+    recent: VecDeque<Instant>,
+    message_count: usize,
+    chatters: HashMap<u64, Counter>,
+    emotes: HashMap<String, Counter>,
+    sub_count: usize,
+    raid_count: usize,
+    cheer_count: usize,
+    bits: u64,
+}
+
+struct Counter {
+    name: String,
+    count: usize,
+}
+
+impl Default for ChatStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChatStats {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            recent: VecDeque::new(),
+            message_count: 0,
+            chatters: HashMap::new(),
+            emotes: HashMap::new(),
+            sub_count: 0,
+            raid_count: 0,
+            cheer_count: 0,
+            bits: 0,
+        }
+    }
+
+    pub fn record(&mut self, event: &TwitchEvent) {
+        self.record_at(event, Instant::now());
+    }
+
+    fn record_at(&mut self, event: &TwitchEvent, now: Instant) {
+        if let TwitchEvent::UserNotice(notice) = event {
+            if notice.is_sub() {
+                self.sub_count += 1;
+            } else if notice.kind() == &UserNoticeKind::Raid {
+                self.raid_count += 1;
+            }
+        }
+
+        let msg = match event.message() {
+            Some(msg) => msg,
+            None => return,
+        };
+
+        self.message_count += 1;
+        if let Some(bits) = msg.bits() {
+            self.cheer_count += 1;
+            self.bits += bits;
+        }
+        self.recent.push_back(now);
+        self.prune(now);
+
+        self.chatters
+            .entry(msg.user_id())
+            .or_insert_with(|| Counter {
+                name: msg.display_name().to_owned(),
+                count: 0,
+            })
+            .count += 1;
+
+        for emote in msg.emotes() {
+            let name = &msg.content()[emote.ranges()[0].clone()];
+            self.emotes
+                .entry(emote.id().to_owned())
+                .or_insert_with(|| Counter {
+                    name: name.to_owned(),
+                    count: 0,
+                })
+                .count += emote.ranges().len();
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some(t) = self.recent.front() {
+            if now.duration_since(*t) < WINDOW {
+                break;
+            }
+            self.recent.pop_front();
+        }
+    }
+
+    /// Messages per minute over the last minute, or since the start if it has
+    /// been running for less than that.
+    pub fn messages_per_minute(&mut self) -> f64 {
+        self.messages_per_minute_at(Instant::now())
+    }
+
+    fn messages_per_minute_at(&mut self, now: Instant) -> f64 {
+        self.prune(now);
+        let window = now.duration_since(self.started_at).min(WINDOW);
+        if window.as_secs_f64() < 1.0 {
+            return self.recent.len() as f64;
+        }
+        self.recent.len() as f64 * WINDOW.as_secs_f64() / window.as_secs_f64()
+    }
+
+    pub fn summary(&mut self, top: usize) -> StatsSummary {
+        self.summary_at(top, Instant::now())
+    }
+
+    fn summary_at(&mut self, top: usize, now: Instant) -> StatsSummary {
+        let top_chatters = self
+            .chatters
+            .values()
+            .sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)))
+            .take(top)
+            .map(|c| ChatterCount {
+                name: c.name.clone(),
+                count: c.count,
+            })
+            .collect();
+        let top_emotes = self
+            .emotes
+            .iter()
+            .sorted_by(|(_, a), (_, b)| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)))
+            .take(top)
+            .map(|(id, c)| EmoteCount {
+                id: id.clone(),
+                name: c.name.clone(),
+                count: c.count,
+            })
+            .collect();
+
+        StatsSummary {
+            elapsed_secs: now.duration_since(self.started_at).as_secs(),
+            messages_per_minute: self.messages_per_minute_at(now),
+            message_count: self.message_count,
+            unique_chatters: self.chatters.len(),
+            sub_count: self.sub_count,
+            raid_count: self.raid_count,
+            cheer_count: self.cheer_count,
+            bits: self.bits,
+            top_chatters,
+            top_emotes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSummary {
+    pub elapsed_secs: u64,
+    pub messages_per_minute: f64,
+    pub message_count: usize,
+    pub unique_chatters: usize,
+    pub sub_count: usize,
+    pub raid_count: usize,
+    pub cheer_count: usize,
+    pub bits: u64,
+    pub top_chatters: Vec<ChatterCount>,
+    pub top_emotes: Vec<EmoteCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatterCount {
+    pub name: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmoteCount {
+    pub id: String,
+    pub name: String,
+    pub count: usize,
+}
+
+impl Display for StatsSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bold = Style::new().bold();
+
+        writeln!(
+            f,
+            "{} {:.1}  {} {}  {} {}",
+            bold.paint("Messages/min:"),
+            self.messages_per_minute,
+            bold.paint("Messages:"),
+            self.message_count,
+            bold.paint("Chatters:"),
+            self.unique_chatters
+        )?;
+        writeln!(
+            f,
+            "{} {}  {} {}  {} {} ({} bits)  {} {}s",
+            bold.paint("Subs:"),
+            self.sub_count,
+            bold.paint("Raids:"),
+            self.raid_count,
+            bold.paint("Cheers:"),
+            self.cheer_count,
+            self.bits,
+            bold.paint("Elapsed:"),
+            self.elapsed_secs
+        )?;
+
+        writeln!(f, "\n{}", bold.paint("Top chatters"))?;
+        for c in &self.top_chatters {
+            writeln!(f, "{:>6}  {}", c.count, Color::Green.paint(&c.name))?;
+        }
+
+        write!(f, "\n{}", bold.paint("Top emotes"))?;
+        for e in &self.top_emotes {
+            write!(f, "\n{:>6}  {}", e.count, Color::Blue.paint(&e.name))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn event(s: &str) -> TwitchEvent {
+        TwitchEvent::try_from(irc::proto::Message::from(s)).unwrap()
+    }
+
+    #[test]
+    fn aggregate() {
+        let start = Instant::now();
+        let mut stats = ChatStats::new();
+        stats.started_at = start;
+
+        let events = [
+            "@display-name=Foo;emotes=25:0-4,6-10;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :Kappa Kappa",
+            "@display-name=Baz;emotes=25:3-7;user-id=2 :baz!baz@baz.tmi.twitch.tv PRIVMSG #bar :hi Kappa",
+            "@bits=100;display-name=Foo;user-id=1 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :Cheer100 hello",
+            "@login=qux;msg-id=resub;system-msg=qux\\ssubscribed;user-id=3 :tmi.twitch.tv USERNOTICE #bar :yay",
+            "@login=quux;msg-id=raid;system-msg=raid;user-id=4 :tmi.twitch.tv USERNOTICE #bar",
+        ];
+        for (i, s) in events.iter().enumerate() {
+            stats.record_at(&event(s), start + Duration::from_secs(20 * i as u64));
+        }
+
+        let summary = stats.summary_at(2, start + Duration::from_secs(90));
+        assert_eq!(summary.message_count, 4);
+        assert_eq!(summary.unique_chatters, 3);
+        assert_eq!(summary.sub_count, 1);
+        assert_eq!(summary.raid_count, 1);
+        assert_eq!(summary.cheer_count, 1);
+        assert_eq!(summary.bits, 100);
+        assert!((summary.messages_per_minute - 2.0).abs() < f64::EPSILON);
+        assert_eq!(summary.top_chatters[0].name, "Foo");
+        assert_eq!(summary.top_chatters[0].count, 2);
+        assert_eq!(summary.top_emotes.len(), 1);
+        assert_eq!(summary.top_emotes[0].name, "Kappa");
+        assert_eq!(summary.top_emotes[0].count, 3);
+    }
+}
@@ -1,31 +1,68 @@
-use crate::{Auth, TwitchStream};
+use crate::{get_current_user, helix, Auth, TwitchStream};
+
+use actix_web::error;
 
-use actix_web::http::HeaderValue;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
 
 #[derive(Serialize)]
 struct QueryParams {
     first: usize,
 }
 
+#[derive(Serialize)]
+struct GameQueryParams<'a> {
+    game_id: &'a str,
+    first: usize,
+}
+
+#[derive(Serialize)]
+struct FollowedQueryParams<'a> {
+    user_id: &'a str,
+    first: usize,
+}
+
 #[derive(Deserialize)]
 struct StreamsResponse {
     data: Vec<TwitchStream>,
 }
 
 pub async fn get_streams(limit: usize, auth: &Auth) -> actix_web::Result<Vec<TwitchStream>> {
-    let mut client_id = HeaderValue::from_str(&auth.client_id)?;
-    client_id.set_sensitive(true);
-
-    let response = actix_web::client::Client::new()
-        .get("https://api.twitch.tv/helix/streams")
-        .query(&QueryParams { first: limit })?
-        .bearer_auth(&auth.oauth_token)
-        .header("client-id", client_id)
-        .send()
-        .await?
-        .json::<StreamsResponse>()
-        .await?;
+    let response: StreamsResponse =
+        helix::get("streams", &QueryParams { first: limit }, auth).await?;
+    Ok(response.data)
+}
+
+/// Lists the most watched streams in the category with `game_id`.
+pub async fn get_game_streams(
+    game_id: &str,
+    limit: usize,
+    auth: &Auth,
+) -> actix_web::Result<Vec<TwitchStream>> {
+    let query = GameQueryParams {
+        game_id,
+        first: limit,
+    };
+    let response: StreamsResponse = helix::get("streams", &query, auth).await?;
+    Ok(response.data)
+}
 
+/// Lists live channels followed by the owner of the user token in `auth`, most
+/// watched first. Requires the `user:read:follows` scope.
+pub async fn get_followed_streams(
+    limit: usize,
+    auth: &Auth,
+) -> actix_web::Result<Vec<TwitchStream>> {
+    let user = get_current_user(auth).await?.ok_or_else(|| {
+        error::ErrorUnauthorized("A user token is required to list followed channels")
+    })?;
+    let query = FollowedQueryParams {
+        user_id: user.id(),
+        first: limit,
+    };
+    let mut response: StreamsResponse = helix::get("streams/followed", &query, auth).await?;
+    response
+        .data
+        .sort_by_key(|stream| Reverse(stream.viewer_count));
     Ok(response.data)
 }
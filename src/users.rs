@@ -0,0 +1,64 @@
+use crate::{helix, Auth};
+
+use actix_web::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+struct QueryParams<'a> {
+    login: &'a str,
+}
+
+#[derive(Deserialize)]
+struct UsersResponse {
+    data: Vec<TwitchUser>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TwitchUser {
+    id: String,
+    login: String,
+    display_name: String,
+}
+
+impl TwitchUser {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+}
+
+/// Looks up a user by login name, returning `None` if there is no such user.
+pub async fn get_user(login: &str, auth: &Auth) -> actix_web::Result<Option<TwitchUser>> {
+    // Helix rejects malformed logins with 400 instead of returning no users.
+    let is_valid = !login.is_empty()
+        && login.len() <= 25
+        && login
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_');
+    if !is_valid {
+        return Ok(None);
+    }
+
+    let response: UsersResponse = helix::get("users", &QueryParams { login }, auth).await?;
+    Ok(response.data.into_iter().next())
+}
+
+/// Returns the user the token in `auth` belongs to, or `None` if it is not a
+/// user token.
+pub async fn get_current_user(auth: &Auth) -> actix_web::Result<Option<TwitchUser>> {
+    // Without a login, Helix rejects app tokens with 400, and invalid ones with 401.
+    match helix::get::<_, UsersResponse>("users", &(), auth).await {
+        Ok(response) => Ok(response.data.into_iter().next()),
+        Err(e) if helix::is_status(&e, &[StatusCode::BAD_REQUEST, StatusCode::UNAUTHORIZED]) => {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
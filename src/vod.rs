@@ -0,0 +1,263 @@
+use crate::{
+    helix,
+    message::{Emote, FallbackColorMap, TwitchMessage},
+    Auth,
+};
+
+use actix_web::http::HeaderValue;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum QueryParams<'a> {
+    Offset { content_offset_seconds: f64 },
+    Cursor { cursor: &'a str },
+}
+
+#[derive(Serialize)]
+struct VideoParams<'a> {
+    id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct VideosResponse {
+    data: Vec<Video>,
+}
+
+#[derive(Deserialize)]
+struct Video {
+    user_login: String,
+}
+
+#[derive(Deserialize)]
+struct CommentsResponse {
+    comments: Vec<Comment>,
+    #[serde(rename = "_next")]
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    #[serde(rename = "_id")]
+    id: String,
+    created_at: Option<DateTime<Utc>>,
+    content_offset_seconds: f64,
+    commenter: Commenter,
+    message: CommentMessage,
+}
+
+#[derive(Deserialize)]
+struct Commenter {
+    #[serde(rename = "_id")]
+    id: String,
+    display_name: Option<String>,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct CommentMessage {
+    body: String,
+    #[serde(default)]
+    fragments: Vec<Fragment>,
+    #[serde(default)]
+    is_action: bool,
+    user_color: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Fragment {
+    text: String,
+    emoticon: Option<Emoticon>,
+}
+
+#[derive(Deserialize)]
+struct Emoticon {
+    emoticon_id: String,
+}
+
+/// A chat message posted during a broadcast, along with its position in the video.
+pub struct VodComment {
+    pub offset: Duration,
+    pub message: TwitchMessage,
+}
+
+impl Comment {
+    /// `channel` is the login of the broadcaster, which comments only give the id of.
+    fn into_vod_comment(self, channel: &str) -> Option<VodComment> {
+        // Fragments carry emote positions, so prefer them when they add up to the body.
+        let mut content = String::new();
+        let mut emotes: Vec<Emote> = Vec::new();
+        for fragment in &self.message.fragments {
+            let range = content.len()..content.len() + fragment.text.len();
+            content.push_str(&fragment.text);
+            if let Some(emoticon) = &fragment.emoticon {
+                match emotes.iter_mut().find(|e| e.id == emoticon.emoticon_id) {
+                    Some(emote) => emote.ranges.push(range),
+                    None => emotes.push(Emote {
+                        id: emoticon.emoticon_id.clone(),
+                        ranges: vec![range],
+                    }),
+                }
+            }
+        }
+        if content != self.message.body {
+            content = self.message.body;
+            emotes.clear();
+        }
+
+        Some(VodComment {
+            offset: Duration::from_secs_f64(self.content_offset_seconds.max(0.0)),
+            message: TwitchMessage {
+                id: Some(self.id),
+                channel: channel.to_owned(),
+                timestamp: self.created_at,
+                user_id: self.commenter.id.parse().ok()?,
+                nick_name: self.commenter.name,
+                content,
+                display_name: self.commenter.display_name,
+                color: self.message.user_color.and_then(|color| color.parse().ok()),
+                is_action: self.message.is_action,
+                emotes,
+                badges: Vec::new(),
+                bits: None,
+                is_highlighted: false,
+                reward_id: None,
+                is_first_message: false,
+            },
+        })
+    }
+}
+
+/// Pages through the chat replay of a video.
+pub struct VodChat {
+    video_id: String,
+    api_url: String,
+    auth: Auth,
+    /// Login of the broadcaster, looked up with the first page.
+    channel: Option<String>,
+    cursor: Option<String>,
+    offset: Option<Duration>,
+    color_map: FallbackColorMap,
+}
+
+impl VodChat {
+    /// Starts at the beginning of the video. `api_url` is the base URL of a
+    /// server providing the v5 `videos/{id}/comments` endpoint. Twitch shut
+    /// down its own in 2022, so there is no default.
+    pub fn new(video_id: &str, api_url: &str, auth: &Auth) -> Self {
+        Self {
+            video_id: video_id.to_owned(),
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            auth: auth.clone(),
+            channel: None,
+            cursor: None,
+            offset: Some(Duration::from_secs(0)),
+            color_map: FallbackColorMap::default(),
+        }
+    }
+
+    /// Makes the next page start at `offset` into the video.
+    pub fn seek(&mut self, offset: Duration) {
+        self.cursor = None;
+        self.offset = Some(offset);
+    }
+
+    /// Fetches the next page of comments, or returns `None` at the end of the video.
+    pub async fn next_page(&mut self) -> actix_web::Result<Option<Vec<VodComment>>> {
+        let query = match (&self.cursor, self.offset) {
+            (Some(cursor), _) => QueryParams::Cursor { cursor },
+            (None, Some(offset)) => QueryParams::Offset {
+                content_offset_seconds: offset.as_secs_f64(),
+            },
+            (None, None) => return Ok(None),
+        };
+
+        let channel = match &self.channel {
+            Some(channel) => channel.clone(),
+            None => {
+                let videos: VideosResponse =
+                    helix::get("videos", &VideoParams { id: &self.video_id }, &self.auth).await?;
+                let channel = videos
+                    .data
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| actix_web::error::ErrorNotFound("No such video"))?
+                    .user_login;
+                self.channel = Some(channel.clone());
+                channel
+            }
+        };
+
+        let mut client_id = HeaderValue::from_str(&self.auth.client_id)?;
+        client_id.set_sensitive(true);
+
+        let response = actix_web::client::Client::new()
+            .get(format!(
+                "{}/videos/{}/comments",
+                self.api_url, self.video_id
+            ))
+            .query(&query)?
+            .header("client-id", client_id)
+            .header("accept", "application/vnd.twitchtv.v5+json")
+            .send()
+            .await?
+            .json::<CommentsResponse>()
+            .limit(16 * 1024 * 1024)
+            .await?;
+
+        self.offset = None;
+        self.cursor = response.next;
+
+        let color_map = &mut self.color_map;
+        let comments = response
+            .comments
+            .into_iter()
+            .filter_map(|comment| comment.into_vod_comment(&channel))
+            .map(|mut comment| {
+                color_map.fill(&mut comment.message);
+                comment
+            })
+            .collect();
+        Ok(Some(comments))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_comment() {
+        let comment: Comment = serde_json::from_str(
+            r##"{
+                "_id": "abc",
+                "channel_id": "1337",
+                "created_at": "2021-06-01T12:34:56.789Z",
+                "content_offset_seconds": 83.5,
+                "commenter": {"_id": "42", "display_name": "Foo", "name": "foo"},
+                "message": {
+                    "body": "Kappa hi Kappa",
+                    "fragments": [
+                        {"text": "Kappa", "emoticon": {"emoticon_id": "25"}},
+                        {"text": " hi "},
+                        {"text": "Kappa", "emoticon": {"emoticon_id": "25"}}
+                    ],
+                    "is_action": false,
+                    "user_color": "#FF0000"
+                }
+            }"##,
+        )
+        .unwrap();
+
+        let comment = comment.into_vod_comment("bar").unwrap();
+        assert_eq!(comment.offset, Duration::from_millis(83500));
+        assert_eq!(comment.message.channel(), "bar");
+        assert_eq!(comment.message.user_id(), 42);
+        assert_eq!(comment.message.display_name(), "Foo");
+        assert_eq!(comment.message.content(), "Kappa hi Kappa");
+        assert_eq!(comment.message.emotes()[0].ranges(), &[0..5, 9..14]);
+        assert_eq!(comment.message.color().unwrap().to_string(), "#FF0000");
+    }
+}
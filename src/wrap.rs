@@ -0,0 +1,166 @@
+use ansi_term::Style;
+use std::fmt::{self, Write};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Writes styled `spans` folded to lines at most `width` columns wide.
+///
+/// Lines are broken at whitespace where possible, and words longer than a line
+/// are broken at grapheme boundaries. Continuation lines are indented by `indent`
+/// columns so that they line up under the message body.
+pub(crate) fn write_wrapped<W: Write>(
+    w: &mut W,
+    spans: &[(Style, &str)],
+    width: usize,
+    indent: usize,
+) -> fmt::Result {
+    let width = width.max(1);
+    let indent = indent.min(width / 2);
+
+    let mut words: Vec<Vec<(Style, &str)>> = Vec::new();
+    let mut prev_is_whitespace = None;
+    for &(style, text) in spans {
+        for grapheme in text.graphemes(true) {
+            let is_whitespace = grapheme.chars().all(char::is_whitespace);
+            if prev_is_whitespace != Some(is_whitespace) {
+                words.push(Vec::new());
+                prev_is_whitespace = Some(is_whitespace);
+            }
+            words.last_mut().unwrap().push((style, grapheme));
+        }
+    }
+
+    let mut line = LineWriter::new(w, indent);
+    let mut pending_space: &[(Style, &str)] = &[];
+    for word in &words {
+        if is_whitespace(word) {
+            pending_space = word;
+            continue;
+        }
+
+        let word_width = graphemes_width(word);
+        let space_width = graphemes_width(pending_space);
+        if line.col + space_width + word_width <= width {
+            line.write_graphemes(pending_space)?;
+        } else if line.col > indent && indent + word_width <= width {
+            line.new_line()?;
+        } else {
+            for &(style, grapheme) in pending_space {
+                if line.col + grapheme.width() > width {
+                    break;
+                }
+                line.write(style, grapheme)?;
+            }
+        }
+        pending_space = &[];
+
+        for &(style, grapheme) in word {
+            if line.col > indent && line.col + grapheme.width() > width {
+                line.new_line()?;
+            }
+            line.write(style, grapheme)?;
+        }
+    }
+
+    line.flush()
+}
+
+fn is_whitespace(word: &[(Style, &str)]) -> bool {
+    word.first()
+        .map(|(_, g)| g.chars().all(char::is_whitespace))
+        .unwrap_or(false)
+}
+
+fn graphemes_width(graphemes: &[(Style, &str)]) -> usize {
+    graphemes.iter().map(|(_, g)| g.width()).sum()
+}
+
+/// Buffers consecutive graphemes sharing a style so that each run is painted once.
+struct LineWriter<'a, W: Write> {
+    w: &'a mut W,
+    indent: usize,
+    col: usize,
+    style: Style,
+    buf: String,
+}
+
+impl<'a, W: Write> LineWriter<'a, W> {
+    fn new(w: &'a mut W, indent: usize) -> Self {
+        Self {
+            w,
+            indent,
+            col: 0,
+            style: Style::new(),
+            buf: String::new(),
+        }
+    }
+
+    fn write(&mut self, style: Style, grapheme: &str) -> fmt::Result {
+        if style != self.style {
+            self.flush()?;
+            self.style = style;
+        }
+        self.buf.push_str(grapheme);
+        self.col += grapheme.width();
+        Ok(())
+    }
+
+    fn write_graphemes(&mut self, graphemes: &[(Style, &str)]) -> fmt::Result {
+        for &(style, grapheme) in graphemes {
+            self.write(style, grapheme)?;
+        }
+        Ok(())
+    }
+
+    fn new_line(&mut self) -> fmt::Result {
+        self.flush()?;
+        write!(self.w, "\n{:1$}", "", self.indent)?;
+        self.col = self.indent;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> fmt::Result {
+        if !self.buf.is_empty() {
+            write!(self.w, "{}", self.style.paint(&self.buf))?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap(spans: &[(Style, &str)], width: usize, indent: usize) -> String {
+        let mut s = String::new();
+        write_wrapped(&mut s, spans, width, indent).unwrap();
+        s
+    }
+
+    #[test]
+    fn wrap_words() {
+        let plain = Style::new();
+        assert_eq!(
+            wrap(&[(plain, "foo: "), (plain, "a bc def ghij")], 10, 5),
+            "foo: a bc\n     def\n     ghij"
+        );
+        assert_eq!(
+            wrap(&[(plain, "foo: "), (plain, "abcdefghijkl")], 10, 5),
+            "foo: abcde\n     fghij\n     kl"
+        );
+    }
+
+    #[test]
+    fn wrap_wide_graphemes() {
+        let plain = Style::new();
+        assert_eq!(
+            wrap(&[(plain, "ab: "), (plain, "日本語のテキスト")], 10, 4),
+            "ab: 日本語\n    のテキ\n    スト"
+        );
+        assert_eq!(
+            wrap(&[(plain, "ab: "), (plain, "😀😀😀 e\u{301}e\u{301}")], 8, 4),
+            "ab: 😀😀\n    😀\n    e\u{301}e\u{301}"
+        );
+    }
+}
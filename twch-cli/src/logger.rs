@@ -0,0 +1,377 @@
+use twch::{archive::Archive, event::TwitchEvent, ChannelError};
+
+use chrono::{NaiveDate, Utc};
+use flate2::{write::GzEncoder, Compression};
+use futures::StreamExt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow::anyhow!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+impl LogFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Text => "log",
+            Self::Json => "ndjson",
+        }
+    }
+
+    fn format(self, event: &TwitchEvent) -> String {
+        match self {
+            Self::Text => {
+                let time = event
+                    .timestamp()
+                    .unwrap_or_else(Utc::now)
+                    .format("%Y-%m-%d %H:%M:%S");
+                let mut line = String::new();
+                match event {
+                    TwitchEvent::UserNotice(notice) => {
+                        line.push_str(&format!("[{}] -- {}\n", time, notice.system_message()));
+                    }
+                    TwitchEvent::Moderation(moderation) => {
+                        line.push_str(&format!("[{}] -- {}\n", time, moderation));
+                    }
+                    TwitchEvent::RoomState(state) => {
+                        line.push_str(&format!("[{}] -- {}\n", time, state));
+                    }
+                    TwitchEvent::Notice(notice) => {
+                        line.push_str(&format!("[{}] -- {}\n", time, notice.message()));
+                    }
+                    TwitchEvent::Message(_) => (),
+                }
+                if let Some(msg) = event.message() {
+                    if msg.is_action() {
+                        line.push_str(&format!(
+                            "[{}] * {} {}\n",
+                            time,
+                            msg.display_name(),
+                            msg.content()
+                        ));
+                    } else if let Some(bits) = msg.bits() {
+                        line.push_str(&format!(
+                            "[{}] <{}> [{} bits] {}\n",
+                            time,
+                            msg.display_name(),
+                            bits,
+                            msg.content()
+                        ));
+                    } else {
+                        line.push_str(&format!(
+                            "[{}] <{}> {}\n",
+                            time,
+                            msg.display_name(),
+                            msg.content()
+                        ));
+                    }
+                }
+                line
+            }
+            Self::Json => {
+                let mut line = serde_json::to_string(event).unwrap();
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+pub struct LogConfig {
+    pub dir: PathBuf,
+    pub format: LogFormat,
+    pub max_size: Option<u64>,
+    pub compress: bool,
+    pub archive: Option<Archive>,
+}
+
+/// Parses a size such as `512`, `64K`, `10M` or `1G`.
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(anyhow::anyhow!("Invalid size: {}", s)),
+    };
+    Ok(digits.parse::<u64>()? * multiplier)
+}
+
+/// Logs chats of `channels` until writing fails, reconnecting whenever a
+/// connection is lost.
+pub async fn run(channels: &[String], config: &LogConfig) -> anyhow::Result<()> {
+    futures::future::try_join_all(channels.iter().map(|channel| log_channel(channel, config)))
+        .await?;
+    Ok(())
+}
+
+async fn log_channel(channel: &str, config: &LogConfig) -> anyhow::Result<()> {
+    let mut log = ChannelLog::new(channel, config);
+    let mut delay = MIN_RECONNECT_DELAY;
+
+    loop {
+        match twch::TwitchChannelStream::new(channel).await {
+            Ok(mut stream) => {
+                eprintln!("Joined #{}", channel);
+                delay = MIN_RECONNECT_DELAY;
+                while let Some(event) = stream.next().await {
+                    match event {
+                        Ok(event) => {
+                            log.write(&event)?;
+                            if let Some(archive) = &config.archive {
+                                archive.insert(&event)?;
+                            }
+                        }
+                        Err(e) => eprintln!("Disconnected from #{}: {}", channel, e),
+                    }
+                }
+            }
+            // Suspended or nonexistent channels are retried too, at the maximum delay.
+            Err(e @ ChannelError::JoinFailed(_)) | Err(e @ ChannelError::JoinTimeout(_)) => {
+                eprintln!("Failed to join #{}: {}", channel, e);
+                delay = MAX_RECONNECT_DELAY;
+            }
+            Err(e) => eprintln!("Failed to join #{}: {}", channel, e),
+        }
+
+        actix_web::rt::time::delay_for(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Per-channel, per-day log files, rotated when they grow beyond `max_size`.
+struct ChannelLog<'a> {
+    channel: &'a str,
+    config: &'a LogConfig,
+    current: Option<LogFile>,
+}
+
+struct LogFile {
+    date: NaiveDate,
+    part: usize,
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl<'a> ChannelLog<'a> {
+    fn new(channel: &'a str, config: &'a LogConfig) -> Self {
+        Self {
+            channel,
+            config,
+            current: None,
+        }
+    }
+
+    fn write(&mut self, event: &TwitchEvent) -> io::Result<()> {
+        let line = self.config.format.format(event);
+        let today = Utc::now().naive_utc().date();
+
+        let needs_rotation = match &self.current {
+            Some(current) => {
+                current.date != today
+                    || self.config.max_size.map_or(false, |max_size| {
+                        current.size > 0 && current.size + line.len() as u64 > max_size
+                    })
+            }
+            None => true,
+        };
+        if needs_rotation {
+            self.rotate(today)?;
+        }
+
+        let current = self.current.as_mut().unwrap();
+        current.file.write_all(line.as_bytes())?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self, date: NaiveDate) -> io::Result<()> {
+        let mut part = 0;
+        if let Some(prev) = self.current.take() {
+            if prev.date == date {
+                part = prev.part + 1;
+            }
+            drop(prev.file);
+            if self.config.compress {
+                compress_in_background(prev.path);
+            }
+        }
+
+        let dir = self.config.dir.join(self.channel);
+        fs::create_dir_all(&dir)?;
+
+        loop {
+            let path = dir.join(file_name(date, part, self.config.format.extension()));
+            let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            let is_full = self.config.max_size.map_or(false, |max| size >= max);
+            if is_full || gz_path(&path).exists() {
+                part += 1;
+                continue;
+            }
+
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            self.current = Some(LogFile {
+                date,
+                part,
+                path,
+                file,
+                size,
+            });
+            return Ok(());
+        }
+    }
+}
+
+fn file_name(date: NaiveDate, part: usize, extension: &str) -> String {
+    if part == 0 {
+        format!("{}.{}", date, extension)
+    } else {
+        format!("{}.{}.{}", date, part, extension)
+    }
+}
+
+fn gz_path(path: &Path) -> PathBuf {
+    let mut s = path.as_os_str().to_owned();
+    s.push(".gz");
+    s.into()
+}
+
+fn compress_in_background(path: PathBuf) {
+    std::thread::spawn(move || {
+        let compress = || -> io::Result<()> {
+            let mut src = File::open(&path)?;
+            let dst = File::create(gz_path(&path))?;
+            let mut encoder = GzEncoder::new(dst, Compression::default());
+            io::copy(&mut src, &mut encoder)?;
+            encoder.finish()?;
+            fs::remove_file(&path)
+        };
+        if let Err(e) = compress() {
+            eprintln!("Failed to compress {}: {}", path.display(), e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn config(dir: &Path, max_size: Option<u64>) -> LogConfig {
+        LogConfig {
+            dir: dir.to_owned(),
+            format: LogFormat::Text,
+            max_size,
+            compress: false,
+            archive: None,
+        }
+    }
+
+    fn event(content: &str) -> TwitchEvent {
+        let line = format!(
+            "@user-id=1;tmi-sent-ts=0 :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :{}",
+            content
+        );
+        TwitchEvent::try_from(irc::proto::Message::from(line.as_str())).unwrap()
+    }
+
+    fn current_name(log: &ChannelLog) -> String {
+        let path = &log.current.as_ref().unwrap().path;
+        path.file_name().unwrap().to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("512B").unwrap(), 512);
+        assert_eq!(parse_size("64K").unwrap(), 64 << 10);
+        assert_eq!(parse_size("10mb").unwrap(), 10 << 20);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("10T").is_err());
+        assert!(parse_size("1.5M").is_err());
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let line_len = LogFormat::Text.format(&event("hi")).len() as u64;
+        let config = config(dir.path(), Some(line_len * 2));
+        let mut log = ChannelLog::new("bar", &config);
+
+        for _ in 0..3 {
+            log.write(&event("hi")).unwrap();
+        }
+        let today = Utc::now().naive_utc().date();
+        let size = |name: String| {
+            fs::metadata(dir.path().join("bar").join(name))
+                .unwrap()
+                .len()
+        };
+        assert_eq!(size(file_name(today, 0, "log")), line_len * 2);
+        assert_eq!(size(file_name(today, 1, "log")), line_len);
+        assert_eq!(current_name(&log), file_name(today, 1, "log"));
+    }
+
+    #[test]
+    fn rotate_by_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path(), None);
+        let mut log = ChannelLog::new("bar", &config);
+        let day = NaiveDate::from_ymd_opt(2021, 6, 1).unwrap();
+
+        log.rotate(day).unwrap();
+        assert_eq!(current_name(&log), "2021-06-01.log");
+        log.rotate(day.succ_opt().unwrap()).unwrap();
+        assert_eq!(current_name(&log), "2021-06-02.log");
+    }
+
+    #[test]
+    fn skip_full_and_compressed_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path(), Some(10));
+        let channel_dir = dir.path().join("bar");
+        fs::create_dir_all(&channel_dir).unwrap();
+        fs::write(channel_dir.join("2021-06-01.log"), "0123456789").unwrap();
+        fs::write(channel_dir.join("2021-06-01.1.log.gz"), "").unwrap();
+        fs::write(channel_dir.join("2021-06-01.2.log"), "01234").unwrap();
+
+        // Parts that are neither full nor compressed are appended to
+        let mut log = ChannelLog::new("bar", &config);
+        let day = NaiveDate::from_ymd_opt(2021, 6, 1).unwrap();
+        log.rotate(day).unwrap();
+        assert_eq!(current_name(&log), "2021-06-01.2.log");
+        assert_eq!(log.current.as_ref().unwrap().size, 5);
+
+        log.rotate(day).unwrap();
+        assert_eq!(current_name(&log), "2021-06-01.3.log");
+    }
+}
@@ -1,5 +1,28 @@
+mod logger;
+
+use logger::{LogConfig, LogFormat};
+use twch::{
+    archive::{Archive, HistoryQuery},
+    config::{Config, ConfigError, Source},
+    credentials::Credentials,
+    event::TwitchEvent,
+    filter::EventFilter,
+    message::{ColorMode, RenderOptions},
+    oauth::OAuthClient,
+    stats::ChatStats,
+    vod::VodChat,
+};
+
+use ansi_term::{Color, Style};
+use chrono::Utc;
 use futures::StreamExt;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
+use terminal_size::Width;
 
 #[derive(StructOpt)]
 struct Opt {
@@ -7,41 +30,274 @@ struct Opt {
     cmd: Option<Command>,
 }
 
+/// Rendering flags, overriding the `[display]` section of the config file.
+#[derive(StructOpt)]
+struct DisplayOpt {
+    /// Wrap lines at this width [default: terminal width]
+    #[structopt(short, long)]
+    width: Option<usize>,
+
+    /// "none", "16", "256" or "truecolor" [default: truecolor]
+    #[structopt(long)]
+    color: Option<ColorMode>,
+
+    /// Emphasize this word in messages (can be repeated)
+    #[structopt(long = "highlight", number_of_values = 1)]
+    highlights: Vec<String>,
+
+    /// Prefix messages with the time they were sent (UTC)
+    #[structopt(long)]
+    timestamps: bool,
+
+    /// Show badges such as [moderator] before names
+    #[structopt(long)]
+    badges: bool,
+}
+
 #[derive(StructOpt)]
 enum Command {
     List {
-        #[structopt(short, default_value = "10")]
-        n: usize,
+        /// [default: 10]
+        #[structopt(short)]
+        n: Option<usize>,
+
+        /// Only show streams in this category, e.g. "Just Chatting"
+        #[structopt(short, long)]
+        game: Option<String>,
+    },
+    /// Show the most watched categories, or search them
+    Games {
+        query: Option<String>,
+
+        /// [default: 10]
+        #[structopt(short)]
+        n: Option<usize>,
+    },
+    /// Show live channels you follow (requires `twch login`)
+    Following {
+        /// [default: 10]
+        #[structopt(short)]
+        n: Option<usize>,
     },
     Search {
         query: String,
 
-        #[structopt(short, default_value = "10")]
-        n: usize,
+        /// [default: 10]
+        #[structopt(short)]
+        n: Option<usize>,
     },
     View {
-        channel: String,
+        /// [default: first channel in the config file]
+        channel: Option<String>,
+
+        #[structopt(flatten)]
+        display: DisplayOpt,
+
+        /// Store chats in the history database
+        #[structopt(long)]
+        archive: bool,
+
+        /// Only show messages of these kinds: highlighted, reward, first, cheer
+        #[structopt(long)]
+        filter: Option<EventFilter>,
+
+        /// How often to refresh the channel info, e.g. 5m, or 0s for never
+        #[structopt(long, default_value = "5m", parse(try_from_str = humantime::parse_duration))]
+        refresh: Duration,
+
+        /// Show up to this many earlier messages if `recent_messages_url` is configured
+        #[structopt(long, default_value = "50")]
+        backlog: usize,
+    },
+    Stats {
+        /// [default: first channel in the config file]
+        channel: Option<String>,
+
+        /// [default: 10]
+        #[structopt(short)]
+        n: Option<usize>,
+    },
+    Log {
+        /// [default: channels in the config file]
+        channels: Vec<String>,
+
+        /// Directory to write logs to
+        #[structopt(short, long, default_value = "logs")]
+        dir: PathBuf,
+
+        /// "text" or "json" (newline-delimited)
+        #[structopt(short, long, default_value = "text")]
+        format: LogFormat,
+
+        /// Start a new file when the current one exceeds this size, e.g. 10M
+        #[structopt(long, parse(try_from_str = logger::parse_size))]
+        max_size: Option<u64>,
+
+        /// Gzip log files once they are rotated
+        #[structopt(long)]
+        compress: bool,
+
+        /// Also store chats in the history database
+        #[structopt(long)]
+        archive: bool,
+    },
+    Vod {
+        video_id: String,
+
+        /// Playback speed multiplier
+        #[structopt(long, default_value = "1")]
+        speed: f64,
+
+        /// Start at this position in the video, e.g. 1h2m3s
+        #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+        start: Option<Duration>,
+
+        #[structopt(flatten)]
+        display: DisplayOpt,
+    },
+    History {
+        /// [default: first channel in the config file]
+        channel: Option<String>,
+
+        /// Only show events of this user
+        #[structopt(short, long)]
+        user: Option<String>,
+
+        /// Only show events containing these words
+        #[structopt(short, long)]
+        grep: Option<String>,
+
+        /// Only show events newer than this, e.g. 1h or 30m
+        #[structopt(short, long, parse(try_from_str = humantime::parse_duration))]
+        since: Option<Duration>,
+
+        /// [default: 100]
+        #[structopt(short)]
+        n: Option<usize>,
     },
+    Config(ConfigCommand),
+    /// Log in with your Twitch account and store the token
+    Login {
+        /// Client ID of your application [default: auth.client_id in the config file]
+        #[structopt(long)]
+        client_id: Option<String>,
+
+        /// Space-separated scopes to request
+        #[structopt(long, default_value = "user:read:follows")]
+        scopes: String,
+    },
+    /// Revoke and forget the stored token
+    Logout,
+}
+
+#[derive(StructOpt)]
+enum ConfigCommand {
+    /// Show the effective configuration and where each value comes from
+    Check,
 }
 
 impl Default for Command {
     fn default() -> Self {
-        Self::List { n: 10 }
+        Self::List {
+            n: None,
+            game: None,
+        }
+    }
+}
+
+impl Command {
+    /// Whether the command calls Helix, which needs a valid token. Chats are
+    /// read anonymously.
+    fn uses_helix(&self) -> bool {
+        matches!(
+            self,
+            Self::List { .. }
+                | Self::Games { .. }
+                | Self::Following { .. }
+                | Self::Search { .. }
+                | Self::View { .. }
+                | Self::Vod { .. }
+        )
     }
 }
 
+/// Replaces an expired stored token using its refresh token, unless a token
+/// is configured explicitly. If the token cannot be validated, e.g. while
+/// offline, it is used as is.
+async fn refresh_credentials(config: &mut Config, path: &Path) -> anyhow::Result<()> {
+    let credentials = match config.credentials() {
+        Some(credentials) if config.auth.oauth_token.is_none() => credentials,
+        _ => return Ok(()),
+    };
+    let oauth = OAuthClient::new(&credentials.client_id, config.auth.auth_url());
+    match oauth.validate(&credentials.access_token).await {
+        Ok(Some(_)) => return Ok(()),
+        Ok(None) => (),
+        Err(e) => {
+            eprintln!("Failed to validate stored token, using it anyway: {}", e);
+            return Ok(());
+        }
+    }
+
+    let expired = || anyhow::anyhow!("Stored token expired, run `twch login` again");
+    let refresh_token = credentials.refresh_token.as_deref().ok_or_else(expired)?;
+    let token = oauth
+        .refresh(refresh_token)
+        .await
+        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+        .ok_or_else(expired)?;
+    let credentials = Credentials {
+        client_id: credentials.client_id.clone(),
+        access_token: token.access_token,
+        refresh_token: token
+            .refresh_token
+            .or_else(|| credentials.refresh_token.clone()),
+        login: credentials.login.clone(),
+    };
+    credentials.save(path)?;
+    config.set_credentials(credentials);
+    Ok(())
+}
+
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
     let opt = Opt::from_args();
-    let auth = twch::Auth::from_env()?;
+    let config_path = config_path();
+    let mut config = match &config_path {
+        Some(path) if path.exists() => Config::load(path)?,
+        _ => Config::from_env()?,
+    };
+    let credentials_path = config
+        .auth
+        .credentials_path
+        .clone()
+        .or_else(default_credentials_path);
+    config.load_credentials(credentials_path.as_deref())?;
+    let cmd = opt.cmd.unwrap_or_default();
+    if let (true, Some(path)) = (cmd.uses_helix(), &credentials_path) {
+        refresh_credentials(&mut config, path).await?;
+    }
+    let config = config;
+    let auth = || config.auth();
 
-    match opt.cmd.unwrap_or_default() {
-        Command::List { n } => {
+    match cmd {
+        Command::List { n, game } => {
+            let n = n.unwrap_or_else(|| config.limits.list());
+            let auth = auth()?;
+            let streams = match game {
+                Some(game) => {
+                    let game = twch::get_game(&game, &auth)
+                        .await
+                        .map_err(|e| anyhow::anyhow!(e.to_string()))?
+                        .ok_or_else(|| anyhow::anyhow!("Unknown game: {}", game))?;
+                    twch::get_game_streams(game.id(), n, &auth).await
+                }
+                None => twch::get_streams(n, &auth).await,
+            };
             println!(
                 "{}",
-                twch::get_streams(n, &auth)
-                    .await
+                streams
                     .map_err(|e| anyhow::anyhow!(e.to_string()))?
                     .into_iter()
                     .map(|stream| format!("{}\n", stream))
@@ -49,10 +305,38 @@ async fn main() -> anyhow::Result<()> {
                     .join("\n")
             );
         }
+        Command::Games { query, n } => {
+            let n = n.unwrap_or_else(|| config.limits.list());
+            let games = match query {
+                Some(query) => twch::search_games(&query, n, &auth()?).await,
+                None => twch::get_top_games(n, &auth()?).await,
+            };
+            for game in games.map_err(|e| anyhow::anyhow!(e.to_string()))? {
+                println!("{}", game);
+            }
+        }
+        Command::Following { n } => {
+            let n = n.unwrap_or_else(|| config.limits.list());
+            let streams = twch::get_followed_streams(n, &auth()?)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            if streams.is_empty() {
+                println!("None of the channels you follow are live");
+            }
+            println!(
+                "{}",
+                streams
+                    .into_iter()
+                    .map(|stream| format!("{}\n", stream))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
         Command::Search { query, n } => {
+            let n = n.unwrap_or_else(|| config.limits.search());
             println!(
                 "{}",
-                twch::search_channels(&query, n, &auth)
+                twch::search_channels(&query, n, &auth()?)
                     .await
                     .map_err(|e| anyhow::anyhow!(e.to_string()))?
                     .into_iter()
@@ -61,17 +345,375 @@ async fn main() -> anyhow::Result<()> {
                     .join("\n")
             );
         }
-        Command::View { channel } => {
-            twch::TwitchChannelStream::new(&channel)
+        Command::View {
+            channel,
+            display,
+            archive,
+            filter,
+            refresh,
+            backlog,
+        } => {
+            let channel = channel_or_default(channel, &config)?;
+            let options = render_options(display, &config);
+            let filter = filter
+                .or_else(|| config.display.filter.clone())
+                .unwrap_or_default();
+            let auth = auth()?;
+            let archive = if archive {
+                Some(open_archive(&config)?)
+            } else {
+                None
+            };
+            let info = twch::get_channel_info(&channel, &auth)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?
+                .ok_or_else(|| anyhow::anyhow!("Unknown channel: {}", channel))?;
+            println!("{}", info);
+
+            if let Some(url) = config
+                .recent_messages_url
+                .as_deref()
+                .filter(|_| backlog > 0)
+            {
+                match twch::get_recent_messages(url, &channel, backlog).await {
+                    Ok(events) => {
+                        let events: Vec<_> = events
+                            .iter()
+                            .filter(|event| filter.matches(event))
+                            .collect();
+                        for event in &events {
+                            println!("{}", event.display(&options));
+                        }
+                        if !events.is_empty() {
+                            println!("--- {} earlier messages, live from here ---", events.len());
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to get recent messages: {}", e),
+                }
+            }
+
+            let mut stream = twch::TwitchChannelStream::new(&channel)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?
+                .fuse();
+            println!(
+                "{}",
+                TwitchEvent::RoomState(stream.get_ref().room_state().clone()).display(&options)
+            );
+            let mut info_updates = twch::watch_channel_info(&channel, refresh, &auth)
+                .boxed_local()
+                .fuse();
+
+            loop {
+                futures::select! {
+                    event = stream.next() => match event {
+                        Some(event) => {
+                            let event = event.map_err(|e| anyhow::anyhow!(e.to_string()))?;
+                            if filter.matches(&event) {
+                                println!("{}", event.display(&options));
+                            }
+                            if let Some(archive) = &archive {
+                                if let Err(e) = archive.insert(&event) {
+                                    eprintln!("Failed to archive: {}", e);
+                                }
+                            }
+                        }
+                        None => break,
+                    },
+                    info = info_updates.next() => match info {
+                        Some(Ok(info)) => println!("{}", info),
+                        Some(Err(e)) => eprintln!("Failed to refresh channel info: {}", e),
+                        None => (),
+                    },
+                }
+            }
+        }
+        Command::Stats { channel, n } => {
+            let channel = channel_or_default(channel, &config)?;
+            let n = n.unwrap_or_else(|| config.limits.stats());
+            let mut stream = twch::TwitchChannelStream::new(&channel)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?
+                .fuse();
+            let mut ticks = actix_web::rt::time::interval(Duration::from_secs(1)).fuse();
+            let mut stats = ChatStats::new();
+
+            loop {
+                futures::select! {
+                    event = stream.next() => match event {
+                        Some(Ok(event)) => stats.record(&event),
+                        Some(Err(e)) => return Err(anyhow::anyhow!(e.to_string())),
+                        None => break,
+                    },
+                    _ = ticks.next() => {
+                        // clear screen and move cursor to top-left
+                        print!("\x1b[2J\x1b[H{}", stats.summary(n));
+                        std::io::stdout().flush()?;
+                    }
+                }
+            }
+        }
+        Command::Log {
+            channels,
+            dir,
+            format,
+            max_size,
+            compress,
+            archive,
+        } => {
+            let channels = if channels.is_empty() {
+                config.channels.clone()
+            } else {
+                channels
+            };
+            if channels.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "No channels given on the command line or in the config file"
+                ));
+            }
+            let log_config = LogConfig {
+                dir,
+                format,
+                max_size,
+                compress,
+                archive: if archive {
+                    Some(open_archive(&config)?)
+                } else {
+                    None
+                },
+            };
+            logger::run(&channels, &log_config).await?;
+        }
+        Command::Vod {
+            video_id,
+            speed,
+            start,
+            display,
+        } => {
+            if !speed.is_finite() || speed <= 0.0 {
+                return Err(anyhow::anyhow!("Speed must be positive"));
+            }
+            let options = render_options(display, &config);
+            let api_url = config
+                .vod_api_url
+                .as_deref()
+                .ok_or(ConfigError::Missing("vod_api_url", "VOD_API_URL"))?;
+            let start = start.unwrap_or_default();
+
+            let mut chat = VodChat::new(&video_id, api_url, &auth()?);
+            chat.seek(start);
+
+            let started_at = Instant::now();
+            while let Some(comments) = chat
+                .next_page()
                 .await
                 .map_err(|e| anyhow::anyhow!(e.to_string()))?
-                .for_each(|msg| {
-                    println!("{}", msg);
-                    futures::future::ready(())
-                })
-                .await;
+            {
+                for comment in comments {
+                    if comment.offset < start {
+                        continue;
+                    }
+                    let due = (comment.offset - start).div_f64(speed);
+                    let elapsed = started_at.elapsed();
+                    if due > elapsed {
+                        actix_web::rt::time::delay_for(due - elapsed).await;
+                    }
+                    println!(
+                        "{} {}",
+                        Style::new().dimmed().paint(format_offset(comment.offset)),
+                        comment.message.display(&options)
+                    );
+                }
+            }
+        }
+        Command::History {
+            channel,
+            user,
+            grep,
+            since,
+            n,
+        } => {
+            let channel = channel_or_default(channel, &config)?;
+            let since = since
+                .map(|since| chrono::Duration::from_std(since).map(|since| Utc::now() - since))
+                .transpose()?;
+            let query = HistoryQuery {
+                channel,
+                user,
+                text: grep,
+                since,
+                limit: n.unwrap_or_else(|| config.limits.history()),
+            };
+            for event in open_archive(&config)?.search(&query)? {
+                println!("{}", event);
+            }
+        }
+        Command::Config(ConfigCommand::Check) => {
+            match &config_path {
+                Some(path) if path.exists() => println!("Config file: {}", path.display()),
+                Some(path) => println!("Config file: {} (not found)", path.display()),
+                None => println!("Config file: none"),
+            }
+            for (key, value, source) in config.entries() {
+                let value = value.unwrap_or_else(|| "(not set)".to_owned());
+                let source = match source {
+                    Source::Default => "default",
+                    Source::File => "file",
+                    Source::Env => "env",
+                    Source::Credentials => "credentials",
+                };
+                println!(
+                    "{:<30} {:<40} {}",
+                    key,
+                    value,
+                    Style::new().dimmed().paint(source)
+                );
+            }
+            if let Err(e) = config.auth() {
+                println!("\n{}", Color::Yellow.paint(e.to_string()));
+            }
+            if let Some(credentials) = config.credentials() {
+                let oauth = OAuthClient::new(&credentials.client_id, config.auth.auth_url());
+                match oauth.validate(&credentials.access_token).await {
+                    Ok(Some(info)) => println!(
+                        "\nLogged in as {} (expires in {})",
+                        info.login.unwrap_or_default(),
+                        humantime::format_duration(Duration::from_secs(info.expires_in))
+                    ),
+                    Ok(None) => println!(
+                        "\n{}",
+                        Color::Yellow.paint("Stored token is invalid or expired, run `twch login`")
+                    ),
+                    Err(e) => println!(
+                        "\n{}",
+                        Color::Yellow.paint(format!("Failed to validate stored token: {}", e))
+                    ),
+                }
+            }
+        }
+        Command::Login { client_id, scopes } => {
+            let path = credentials_path
+                .ok_or_else(|| anyhow::anyhow!("Cannot determine where to store credentials"))?;
+            let client_id = client_id
+                .or_else(|| config.auth.client_id.clone())
+                .ok_or(ConfigError::Missing("auth.client_id", "CLIENT_ID"))?;
+            let oauth = OAuthClient::new(&client_id, config.auth.auth_url());
+            let scopes: Vec<_> = scopes.split_whitespace().collect();
+            let mut code = oauth
+                .request_device_code(&scopes)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+            println!(
+                "Open {} and enter the code {}",
+                code.verification_uri,
+                Color::Yellow.bold().paint(&code.user_code)
+            );
+
+            let expires_at = Instant::now() + Duration::from_secs(code.expires_in);
+            let token = loop {
+                if Instant::now() >= expires_at {
+                    return Err(anyhow::anyhow!("The code expired before it was entered"));
+                }
+                actix_web::rt::time::delay_for(Duration::from_secs(code.interval.max(1))).await;
+                if let Some(token) = oauth
+                    .poll_token(&mut code)
+                    .await
+                    .map_err(|e| anyhow::anyhow!(e.to_string()))?
+                {
+                    break token;
+                }
+            };
+            let info = oauth
+                .validate(&token.access_token)
+                .await
+                .map_err(|e| anyhow::anyhow!(e.to_string()))?
+                .ok_or_else(|| anyhow::anyhow!("Received an invalid token"))?;
+
+            Credentials {
+                client_id,
+                access_token: token.access_token,
+                refresh_token: token.refresh_token,
+                login: info.login.clone(),
+            }
+            .save(&path)?;
+            println!(
+                "Logged in as {}, token stored in {}",
+                Color::Green.paint(info.login.unwrap_or_default()),
+                path.display()
+            );
+        }
+        Command::Logout => {
+            let path = match (credentials_path, config.credentials()) {
+                (Some(path), Some(credentials)) => {
+                    let oauth = OAuthClient::new(&credentials.client_id, config.auth.auth_url());
+                    if let Err(e) = oauth.revoke(&credentials.access_token).await {
+                        eprintln!("Failed to revoke token: {}", e);
+                    }
+                    path
+                }
+                _ => {
+                    println!("Not logged in");
+                    return Ok(());
+                }
+            };
+            Credentials::remove(&path)?;
+            println!("Logged out");
         }
     }
 
     Ok(())
 }
+
+fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(Width(width), _)| width as usize)
+}
+
+fn format_offset(offset: Duration) -> String {
+    let secs = offset.as_secs();
+    format!("[{}:{:02}:{:02}]", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// `$XDG_CONFIG_HOME/twch/config.toml` or the platform's equivalent.
+fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("twch").join("config.toml"))
+}
+
+/// `$XDG_DATA_HOME/twch/credentials.toml` or the platform's equivalent.
+fn default_credentials_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("twch").join("credentials.toml"))
+}
+
+fn channel_or_default(channel: Option<String>, config: &Config) -> anyhow::Result<String> {
+    channel
+        .or_else(|| config.channels.first().cloned())
+        .ok_or_else(|| {
+            anyhow::anyhow!("No channel given on the command line or in the config file")
+        })
+}
+
+fn render_options(opt: DisplayOpt, config: &Config) -> RenderOptions {
+    let mut highlights = config.display.highlights.clone();
+    highlights.extend(opt.highlights);
+    RenderOptions {
+        width: opt.width.or(config.display.width).or_else(terminal_width),
+        color: opt.color.or(config.display.color).unwrap_or_default(),
+        highlights,
+        timestamps: opt.timestamps || config.display.timestamps,
+        badges: opt.badges || config.display.badges,
+    }
+}
+
+/// Opens the history database at the configured path, or in the user's data directory.
+fn open_archive(config: &Config) -> anyhow::Result<Archive> {
+    let path = match &config.archive_path {
+        Some(path) => path.clone(),
+        None => {
+            let dir = dirs::data_dir()
+                .ok_or_else(|| anyhow::anyhow!("Could not find data directory"))?
+                .join("twch");
+            std::fs::create_dir_all(&dir)?;
+            dir.join("archive.db")
+        }
+    };
+    Ok(Archive::open(path)?)
+}
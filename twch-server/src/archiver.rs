@@ -0,0 +1,45 @@
+use crate::backlog::Backlog;
+
+use twch::{archive::Archive, event::TwitchEvent};
+
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// Recent events remembered per channel to tell which ones were already
+/// archived by another stream.
+const SEEN_EVENTS: usize = 500;
+
+/// Stores relayed events in the archive on a thread of its own, so streams
+/// never wait for the database.
+///
+/// Every stream of a channel sends the events it relays, and each event is
+/// only stored once.
+#[derive(Clone)]
+pub struct Archiver {
+    sender: mpsc::Sender<TwitchEvent>,
+}
+
+impl Archiver {
+    pub fn spawn(archive: Arc<Mutex<Archive>>) -> Self {
+        let (sender, receiver) = mpsc::channel::<TwitchEvent>();
+        thread::spawn(move || {
+            let seen = Backlog::new(SEEN_EVENTS);
+            for event in receiver {
+                if !seen.push(&event) {
+                    continue;
+                }
+                if let Err(e) = archive.lock().unwrap().insert(&event) {
+                    eprintln!("Failed to archive: {}", e);
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    pub fn archive(&self, event: &TwitchEvent) {
+        // The thread only stops when every sender is gone
+        let _ = self.sender.send(event.clone());
+    }
+}
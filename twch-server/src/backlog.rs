@@ -0,0 +1,137 @@
+use twch::{event::TwitchEvent, filter::EventFilter};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    sync::{Arc, Mutex},
+};
+
+/// Most recent events of each channel, replayed to clients when they connect.
+///
+/// Every client has its own IRC connection, so every stream of a channel
+/// records the events it relays and duplicates are dropped by [`same_event`].
+/// Channels nobody is watching yet can be seeded with earlier events, e.g. from
+/// a recent-messages service.
+#[derive(Clone)]
+pub struct Backlog {
+    size: usize,
+    channels: Arc<Mutex<HashMap<String, VecDeque<TwitchEvent>>>>,
+}
+
+impl Backlog {
+    /// Keeps up to `size` events per channel, none if it is 0.
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            channels: Default::default(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether no events of `channel` are kept.
+    pub fn is_empty(&self, channel: &str) -> bool {
+        !self.channels.lock().unwrap().contains_key(channel)
+    }
+
+    /// Keeps `event` unless it is already kept. Returns whether it was new.
+    pub fn push(&self, event: &TwitchEvent) -> bool {
+        if self.size == 0 {
+            return true;
+        }
+        let mut channels = self.channels.lock().unwrap();
+        let events = channels.entry(event.channel().to_owned()).or_default();
+        if events.iter().any(|kept| same_event(kept, event)) {
+            return false;
+        }
+        if events.len() == self.size {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+        true
+    }
+
+    /// Up to `limit` of the latest events of `channel` matching `filter`,
+    /// oldest first.
+    pub fn recent(&self, channel: &str, limit: usize, filter: &EventFilter) -> Vec<TwitchEvent> {
+        let channels = self.channels.lock().unwrap();
+        let events = match channels.get(channel) {
+            Some(events) => events,
+            None => return Vec::new(),
+        };
+        let mut recent: Vec<_> = events
+            .iter()
+            .rev()
+            .filter(|event| filter.matches(event))
+            .take(limit)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
+
+/// Whether `a` and `b` are the same event received by different connections.
+///
+/// Messages are compared by id, or by sender, time and content if they have
+/// none, because users without a color get a random one per connection.
+fn same_event(a: &TwitchEvent, b: &TwitchEvent) -> bool {
+    match (a.message(), b.message()) {
+        (Some(x), Some(y)) => {
+            mem::discriminant(a) == mem::discriminant(b)
+                && match (x.id(), y.id()) {
+                    (Some(x_id), Some(y_id)) => x_id == y_id,
+                    _ => {
+                        x.user_id() == y.user_id()
+                            && x.timestamp() == y.timestamp()
+                            && x.content() == y.content()
+                    }
+                }
+        }
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn event(line: &str) -> TwitchEvent {
+        TwitchEvent::try_from(irc::proto::Message::from(line)).unwrap()
+    }
+
+    #[test]
+    fn keep_recent_events_once() {
+        let backlog = Backlog::new(2);
+        for i in 0..3 {
+            let line = format!(
+                "@id={};user-id=1;tmi-sent-ts={} :foo!foo@foo.tmi.twitch.tv PRIVMSG #bar :{}",
+                i, i, i
+            );
+            // Another connection sees the same message with another fallback color
+            let mut seen_twice = event(&line);
+            seen_twice
+                .message_mut()
+                .unwrap()
+                .set_color("#123456".parse().unwrap());
+            assert!(backlog.push(&event(&line)));
+            assert!(!backlog.push(&seen_twice));
+        }
+
+        let contents = |events: Vec<TwitchEvent>| -> Vec<String> {
+            events
+                .iter()
+                .map(|event| event.message().unwrap().content().to_owned())
+                .collect()
+        };
+        let filter = EventFilter::default();
+        assert_eq!(contents(backlog.recent("bar", 10, &filter)), ["1", "2"]);
+        assert_eq!(contents(backlog.recent("bar", 1, &filter)), ["2"]);
+        assert!(backlog.recent("baz", 10, &filter).is_empty());
+        assert!(!backlog.is_empty("bar"));
+        assert!(backlog.is_empty("baz"));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::page::Format;
+use actix_web::{
+    error::{self, InternalError},
+    http::{header, StatusCode},
+    web::Bytes,
+    HttpResponse,
+};
+
+use futures::channel::oneshot;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+type FetchResult = Result<Bytes, (String, StatusCode)>;
+
+#[derive(Default)]
+struct Entry {
+    value: Option<(Bytes, Instant)>,
+    refreshing: bool,
+    waiters: Vec<oneshot::Sender<FetchResult>>,
+}
+
+/// In-memory cache of response bodies shared by all workers.
+///
+/// A body is fresh for `ttl`, after which it is still served for `stale` while
+/// a single request refreshes it in the background. Concurrent requests for a
+/// missing body wait for the same fetch instead of starting their own.
+#[derive(Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    stale: Duration,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+/// A body served from [`ResponseCache`].
+pub struct Cached {
+    body: Bytes,
+    age: Duration,
+    ttl: Duration,
+    stale: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, stale: Duration) -> Self {
+        Self {
+            ttl,
+            stale,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns the body cached under `key`, calling `fetch` to produce it if it
+    /// is missing or stale.
+    pub async fn get<F, Fut>(&self, key: String, fetch: F) -> actix_web::Result<Cached>
+    where
+        F: FnOnce() -> Fut + 'static,
+        Fut: Future<Output = actix_web::Result<String>> + 'static,
+    {
+        let (hit, waiter, should_fetch) = {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.entry(key.clone()).or_default();
+            let age = entry
+                .value
+                .as_ref()
+                .map(|(_, fetched_at)| fetched_at.elapsed());
+            match (&entry.value, age) {
+                (Some((body, _)), Some(age)) if age < self.ttl + self.stale => {
+                    let should_fetch = age >= self.ttl && !entry.refreshing;
+                    entry.refreshing |= should_fetch;
+                    (Some(self.cached(body.clone(), age)), None, should_fetch)
+                }
+                _ => {
+                    let (tx, rx) = oneshot::channel();
+                    entry.waiters.push(tx);
+                    let should_fetch = !entry.refreshing;
+                    entry.refreshing = true;
+                    (None, Some(rx), should_fetch)
+                }
+            }
+        };
+
+        if should_fetch {
+            // Run the fetch in its own task so that it completes even if the
+            // request that started it goes away.
+            let cache = self.clone();
+            let key = key.clone();
+            actix_web::rt::spawn(async move {
+                let result = fetch()
+                    .await
+                    .map(Bytes::from)
+                    .map_err(|e| (e.to_string(), e.as_response_error().status_code()));
+                cache.complete(&key, result);
+            });
+        }
+
+        if let Some(hit) = hit {
+            return Ok(hit);
+        }
+        match waiter.unwrap().await {
+            Ok(Ok(body)) => Ok(self.cached(body, Duration::from_secs(0))),
+            Ok(Err((message, status))) => Err(InternalError::new(message, status).into()),
+            Err(_) => Err(error::ErrorInternalServerError("Request was cancelled")),
+        }
+    }
+
+    fn complete(&self, key: &str, result: FetchResult) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(key) {
+            entry.refreshing = false;
+            if let Ok(body) = &result {
+                entry.value = Some((body.clone(), Instant::now()));
+            }
+            for waiter in entry.waiters.drain(..) {
+                let _ = waiter.send(result.clone());
+            }
+        }
+
+        // Drop bodies that can no longer be served
+        let max_age = self.ttl + self.stale;
+        entries.retain(|_, entry| {
+            entry.refreshing
+                || entry
+                    .value
+                    .as_ref()
+                    .map_or(false, |(_, fetched_at)| fetched_at.elapsed() < max_age)
+        });
+    }
+
+    fn cached(&self, body: Bytes, age: Duration) -> Cached {
+        Cached {
+            body,
+            age,
+            ttl: self.ttl,
+            stale: self.stale,
+        }
+    }
+}
+
+impl Cached {
+    /// Responds with the body, rendered as `format`, along with `Cache-Control`
+    /// and `Age` headers.
+    pub fn into_response(self, format: Format) -> HttpResponse {
+        let max_age = self.ttl.checked_sub(self.age).unwrap_or_default();
+        HttpResponse::Ok()
+            .content_type(format.content_type())
+            .header(header::VARY, "Accept")
+            .header(
+                header::CACHE_CONTROL,
+                format!(
+                    "public, max-age={}, stale-while-revalidate={}",
+                    max_age.as_secs(),
+                    self.stale.as_secs()
+                ),
+            )
+            .header(header::AGE, self.age.as_secs().to_string())
+            .body(self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::LocalBoxFuture;
+    use std::{cell::Cell, rc::Rc};
+
+    /// Fetch returning how many times it was called, or failing if `ok` is false.
+    fn fetch(
+        calls: &Rc<Cell<usize>>,
+        ok: bool,
+    ) -> impl FnOnce() -> LocalBoxFuture<'static, actix_web::Result<String>> {
+        let calls = calls.clone();
+        move || {
+            Box::pin(async move {
+                calls.set(calls.get() + 1);
+                if ok {
+                    Ok(calls.get().to_string())
+                } else {
+                    Err(error::ErrorBadGateway("Failed"))
+                }
+            })
+        }
+    }
+
+    /// Lets spawned fetches run.
+    async fn settle() {
+        actix_web::rt::time::delay_for(Duration::from_millis(10)).await;
+    }
+
+    fn run<F: Future + 'static>(f: F) -> F::Output {
+        actix_web::rt::System::new("test").block_on(f)
+    }
+
+    #[test]
+    fn fetch_concurrent_misses_once() {
+        run(async {
+            let cache = ResponseCache::new(Duration::from_secs(60), Duration::from_secs(60));
+            let calls = Rc::new(Cell::new(0));
+            let (a, b) = futures::join!(
+                cache.get("key".to_owned(), fetch(&calls, true)),
+                cache.get("key".to_owned(), fetch(&calls, true)),
+            );
+            assert_eq!(a.unwrap().body, "1");
+            assert_eq!(b.unwrap().body, "1");
+            assert_eq!(calls.get(), 1);
+
+            let hit = cache.get("key".to_owned(), fetch(&calls, true)).await;
+            assert_eq!(hit.unwrap().body, "1");
+            assert_eq!(calls.get(), 1);
+        });
+    }
+
+    #[test]
+    fn refresh_stale_body_once() {
+        run(async {
+            let cache = ResponseCache::new(Duration::from_secs(0), Duration::from_secs(60));
+            let calls = Rc::new(Cell::new(0));
+            cache
+                .get("key".to_owned(), fetch(&calls, true))
+                .await
+                .unwrap();
+
+            // Both are served the stale body while one of them refreshes it
+            let a = cache.get("key".to_owned(), fetch(&calls, true)).await;
+            let b = cache.get("key".to_owned(), fetch(&calls, true)).await;
+            assert_eq!(a.unwrap().body, "1");
+            assert_eq!(b.unwrap().body, "1");
+            settle().await;
+            assert_eq!(calls.get(), 2);
+
+            let refreshed = cache.get("key".to_owned(), fetch(&calls, true)).await;
+            assert_eq!(refreshed.unwrap().body, "2");
+        });
+    }
+
+    #[test]
+    fn keep_stale_body_if_refresh_fails() {
+        run(async {
+            let cache = ResponseCache::new(Duration::from_secs(0), Duration::from_secs(60));
+            let calls = Rc::new(Cell::new(0));
+            cache
+                .get("key".to_owned(), fetch(&calls, true))
+                .await
+                .unwrap();
+
+            let stale = cache.get("key".to_owned(), fetch(&calls, false)).await;
+            assert_eq!(stale.unwrap().body, "1");
+            settle().await;
+            assert_eq!(calls.get(), 2);
+
+            let stale = cache.get("key".to_owned(), fetch(&calls, true)).await;
+            assert_eq!(stale.unwrap().body, "1");
+        });
+    }
+
+    #[test]
+    fn share_failed_fetch_with_waiters() {
+        run(async {
+            let cache = ResponseCache::new(Duration::from_secs(60), Duration::from_secs(60));
+            let calls = Rc::new(Cell::new(0));
+            let (a, b) = futures::join!(
+                cache.get("key".to_owned(), fetch(&calls, false)),
+                cache.get("key".to_owned(), fetch(&calls, false)),
+            );
+            for result in &[a, b] {
+                let e = result.as_ref().err().unwrap();
+                assert_eq!(e.as_response_error().status_code(), StatusCode::BAD_GATEWAY);
+            }
+            assert_eq!(calls.get(), 1);
+        });
+    }
+}
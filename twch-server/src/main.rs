@@ -1,46 +1,160 @@
-use twch::Auth;
+mod archiver;
+mod backlog;
+mod cache;
+mod metrics;
+mod page;
+mod shutdown;
+mod throttle;
+mod tls;
+
+use archiver::Archiver;
+use backlog::Backlog;
+use cache::ResponseCache;
+use metrics::Metrics;
+use page::{Format, OverlayOptions};
+use shutdown::Shutdown;
+use throttle::{ConnectionGuard, ConnectionLimiter, RequestLimiter};
+use tls::CertReloader;
+use twch::{
+    archive::{Archive, HistoryQuery},
+    config::{LimitsConfig, ServerConfig},
+    event::TwitchEvent,
+    filter::EventFilter,
+    html,
+    message::{ColorMode, RenderOptions},
+    stats::ChatStats,
+    Auth, ChannelError, ChannelInfo, ChatBadges, TwitchChannelStream, TwitchStream,
+};
 
 use actix_web::{
-    error, get, http::header, middleware, web, App, HttpResponse, HttpServer, Responder,
+    dev::{BodyEncoding, Service},
+    error, get,
+    http::{header, ContentEncoding, HeaderMap},
+    middleware, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use futures::{future::Either, FutureExt, Stream, StreamExt};
+use serde::{Deserialize, Deserializer};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::{Duration, Instant},
 };
-use futures::StreamExt;
-use serde::Deserialize;
-use std::{task::Poll, time::Duration};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+struct Opt {
+    /// TOML config file. Environment variables take precedence over it
+    #[structopt(long)]
+    config: Option<PathBuf>,
+}
+
+/// When readiness was last checked, and the result.
+type Readiness = (Instant, Result<(), String>);
 
 #[derive(Clone)]
 struct Config {
     auth: Auth,
     heartbeat_interval: Duration,
+    info_refresh_interval: Duration,
+    archive: Option<Arc<Mutex<Archive>>>,
+    archiver: Option<Archiver>,
+    render_options: RenderOptions,
+    filter: EventFilter,
+    backlog: Backlog,
+    recent_messages_url: Option<String>,
+    limits: LimitsConfig,
+    cache: ResponseCache,
+    requests: RequestLimiter,
+    streams: ConnectionLimiter,
+    trust_proxy: bool,
+    readiness: Arc<Mutex<Option<Readiness>>>,
+    metrics: Metrics,
+    shutdown: Shutdown,
+    shutdown_timeout: Duration,
 }
 
 impl Config {
-    fn from_env() -> anyhow::Result<Self> {
-        let auth = Auth::from_env()?;
-        let heartbeat_interval = if let Ok(value) = std::env::var("HEARTBEAT_INTERVAL") {
-            value.parse()?
+    /// Returns the server settings along with the config, for setting up the
+    /// listeners.
+    fn load(path: Option<&Path>) -> anyhow::Result<(Self, ServerConfig)> {
+        let mut file = match path {
+            Some(path) => twch::config::Config::load(path)?,
+            None => twch::config::Config::from_env()?,
+        };
+        file.load_credentials(None)?;
+
+        let archive = if let Some(path) = &file.archive_path {
+            Some(Arc::new(Mutex::new(Archive::open(path)?)))
         } else {
-            10
+            None
         };
 
-        Ok(Self {
-            auth,
-            heartbeat_interval: Duration::from_secs(heartbeat_interval),
-        })
+        let config = Self {
+            auth: file.auth()?,
+            heartbeat_interval: file.server.heartbeat_interval(),
+            info_refresh_interval: file.server.info_refresh_interval(),
+            archiver: archive.clone().map(Archiver::spawn),
+            archive,
+            render_options: RenderOptions {
+                width: file.display.width,
+                color: file.display.color.unwrap_or_default(),
+                highlights: file.display.highlights.clone(),
+                timestamps: file.display.timestamps,
+                badges: file.display.badges,
+            },
+            filter: file.display.filter.clone().unwrap_or_default(),
+            backlog: Backlog::new(file.server.backlog_size()),
+            recent_messages_url: file.recent_messages_url.clone(),
+            limits: file.limits.clone(),
+            cache: ResponseCache::new(file.server.cache_ttl(), file.server.cache_stale()),
+            requests: RequestLimiter::new(file.server.requests_per_minute()),
+            streams: ConnectionLimiter::new(
+                file.server.max_streams(),
+                file.server.max_streams_per_ip(),
+            ),
+            trust_proxy: file.server.trust_proxy,
+            readiness: Default::default(),
+            metrics: Metrics::default(),
+            shutdown: Shutdown::default(),
+            shutdown_timeout: file.server.shutdown_timeout(),
+        };
+        Ok((config, file.server))
     }
 }
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let config = Config::from_env().unwrap();
-
-    let http_addr = std::env::var("HTTP_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_owned());
-    eprintln!("Listening on http://{}", http_addr);
+    let opt = Opt::from_args();
+    let (config, server_config) = Config::load(opt.config.as_deref())?;
+    let http_addr = server_config.http_addr().to_owned();
+    let tls = match server_config.tls()? {
+        Some((cert, key)) => Some(CertReloader::load(cert, key)?),
+        None => None,
+    };
+    let shutdown = config.shutdown.clone();
+    let shutdown_timeout = config.shutdown_timeout;
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
+        let requests = config.requests.clone();
+        let trust_proxy = config.trust_proxy;
         App::new()
+            .wrap_fn(move |req, srv| {
+                // Probes and scrapes come often from few addresses
+                if UNLIMITED_PATHS.contains(&req.path()) {
+                    return Either::Left(srv.call(req));
+                }
+                let ip = peer_ip(req.headers(), req.peer_addr(), trust_proxy);
+                match requests.check(ip) {
+                    Ok(()) => Either::Left(srv.call(req)),
+                    Err(e) => Either::Right(futures::future::ok(req.error_response(e))),
+                }
+            })
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
             .wrap(middleware::NormalizePath::new(
@@ -51,40 +165,224 @@ async fn main() -> std::io::Result<()> {
                     .header(header::CONTENT_TYPE, "text/plain; charset=utf-8"),
             )
             .data(config.clone())
+            .service(get_health)
+            .service(get_readiness)
+            .service(get_metrics)
             .service(get_streams)
+            // Listings are under `/-/`, which cannot be a channel name
+            .service(get_followed_streams)
             .service(search_channels)
+            .service(get_games)
+            .service(get_game_streams)
             .service(start_channel_stream)
+            .service(start_channel_events)
+            .service(get_channel_overlay)
+            .service(get_channel_stats)
+            .service(get_channel_history)
     })
-    .bind(http_addr)?
-    .run()
-    .await
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout.as_secs());
+    let server = match &tls {
+        Some(cert) => {
+            eprintln!("Listening on https://{}", http_addr);
+            server.bind_rustls(&http_addr, cert.server_config())?
+        }
+        None => {
+            eprintln!("Listening on http://{}", http_addr);
+            server.bind(&http_addr)?
+        }
+    }
+    .run();
+
+    if let Some(cert) = tls.clone() {
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(CERT_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                match cert.reload_if_changed() {
+                    Ok(true) => eprintln!("Reloaded TLS certificate"),
+                    Ok(false) => (),
+                    // Keep serving the previous certificate, and retry once the
+                    // files change again
+                    Err(e) => eprintln!("Failed to reload TLS certificate: {:#}", e),
+                }
+            }
+        });
+    }
+
+    let redirect = match &server_config.redirect_addr {
+        Some(redirect_addr) if tls.is_some() => {
+            let https_port = http_addr
+                .rsplit(':')
+                .next()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(443);
+            eprintln!("Redirecting http://{} to HTTPS", redirect_addr);
+            let redirect = HttpServer::new(move || {
+                App::new()
+                    .wrap(middleware::Logger::default())
+                    .default_service(web::to(move |req: HttpRequest| {
+                        futures::future::ready(redirect_to_https(&req, https_port))
+                    }))
+            })
+            .disable_signals()
+            .bind(redirect_addr)?
+            .run();
+            actix_web::rt::spawn({
+                let redirect = redirect.clone();
+                async move {
+                    if let Err(e) = redirect.await {
+                        eprintln!("Redirect listener failed: {}", e);
+                    }
+                }
+            });
+            Some(redirect)
+        }
+        Some(_) => {
+            anyhow::bail!("server.redirect_addr requires server.tls_cert and server.tls_key")
+        }
+        None => None,
+    };
+
+    actix_web::rt::spawn({
+        let server = server.clone();
+        async move {
+            wait_for_signal().await;
+            eprintln!("Shutting down");
+            // Streams say goodbye and close while the server stops accepting
+            // connections and waits for them.
+            shutdown.trigger();
+            if let Some(redirect) = redirect {
+                redirect.stop(false).await;
+            }
+            server.stop(true).await;
+        }
+    });
+    server.await?;
+    Ok(())
 }
 
-#[derive(Deserialize)]
-#[serde(default)]
-struct GetStreamsQueryParams {
-    limit: usize,
+/// Waits for SIGTERM or Ctrl-C.
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            let terminate = Box::pin(terminate.recv());
+            let interrupt = Box::pin(actix_web::rt::signal::ctrl_c());
+            futures::future::select(terminate, interrupt).await;
+            return;
+        }
+    }
+    let _ = actix_web::rt::signal::ctrl_c().await;
 }
 
-impl Default for GetStreamsQueryParams {
-    fn default() -> Self {
-        Self { limit: 10 }
+/// Redirects to the same path on the HTTPS listener at `https_port`.
+fn redirect_to_https(req: &HttpRequest, https_port: u16) -> HttpResponse {
+    let info = req.connection_info();
+    let host = info.host();
+    // Drop the port, but not the colons of an IPv6 address
+    let host = match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    };
+    let path = req.uri().path_and_query().map_or("/", |path| path.as_str());
+    let location = if https_port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, https_port, path)
+    };
+    HttpResponse::PermanentRedirect()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+/// Responds as long as the server is running.
+#[get("/healthz")]
+async fn get_health() -> impl Responder {
+    HttpResponse::Ok().body("ok\n")
+}
+
+/// Responds with 503 if Helix rejects the configured credentials. The result
+/// is reused for [`READINESS_TTL`] so that probes do not use up the rate limit.
+#[get("/readyz")]
+async fn get_readiness(config: web::Data<Config>) -> impl Responder {
+    let cached = config
+        .readiness
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|(checked_at, _)| checked_at.elapsed() < READINESS_TTL);
+    let result = match cached {
+        Some((_, result)) => result,
+        None => {
+            let result = twch::get_streams(1, &config.auth)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string());
+            *config.readiness.lock().unwrap() = Some((Instant::now(), result.clone()));
+            result
+        }
+    };
+    match result {
+        Ok(()) => HttpResponse::Ok().body("ok\n"),
+        Err(e) => HttpResponse::ServiceUnavailable().body(format!("{}\n", e)),
     }
 }
 
+#[get("/metrics")]
+async fn get_metrics(config: web::Data<Config>) -> impl Responder {
+    let body = config
+        .metrics
+        .render(config.streams.active(), &config.auth.helix_metrics());
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body)
+}
+
+#[derive(Deserialize)]
+struct GetStreamsQueryParams {
+    limit: Option<usize>,
+}
+
 #[get("/")]
 async fn get_streams(
+    req: HttpRequest,
     params: web::Query<GetStreamsQueryParams>,
     config: web::Data<Config>,
 ) -> actix_web::Result<impl Responder> {
-    let body = twch::get_streams(params.limit, &config.auth)
-        .await?
-        .into_iter()
-        .map(|stream| format!("{}\n", stream))
-        .collect::<Vec<_>>()
-        .join("\n");
+    let format = Format::of(&req);
+    let limit = params.limit.unwrap_or_else(|| config.limits.list());
+    let auth = config.auth.clone();
+    let cached = config
+        .cache
+        .get(
+            format!("{} /?limit={}", format.as_str(), limit),
+            move || async move {
+                let streams = twch::get_streams(limit, &auth).await?;
+                Ok(format_streams(format, "Top streams", streams))
+            },
+        )
+        .await?;
 
-    Ok(HttpResponse::Ok().body(body))
+    Ok(cached.into_response(format))
+}
+
+/// Lists live channels followed by the owner of the configured user token.
+#[get("/-/following")]
+async fn get_followed_streams(
+    req: HttpRequest,
+    params: web::Query<GetStreamsQueryParams>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    let format = Format::of(&req);
+    let limit = params.limit.unwrap_or_else(|| config.limits.list());
+    let streams = twch::get_followed_streams(limit, &config.auth).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .header(header::VARY, "Accept")
+        .body(format_streams(format, "Following", streams)))
 }
 
 #[derive(Deserialize)]
@@ -94,61 +392,601 @@ struct SearchChannelsQueryParams {
     limit: Option<usize>,
 }
 
-#[get("/search")]
+#[get("/-/search")]
 async fn search_channels(
+    req: HttpRequest,
     params: web::Query<SearchChannelsQueryParams>,
     config: web::Data<Config>,
 ) -> actix_web::Result<impl Responder> {
-    let body = twch::search_channels(&params.query, params.limit.unwrap_or(10), &config.auth)
+    let format = Format::of(&req);
+    let limit = params.limit.unwrap_or_else(|| config.limits.search());
+    let query = params.into_inner().query;
+    let key = format!("{} /-/search?q={}&limit={}", format.as_str(), query, limit);
+    let auth = config.auth.clone();
+    let cached = config
+        .cache
+        .get(key, move || async move {
+            let streams = twch::search_channels(&query, limit, &auth).await?;
+            Ok(format_streams(
+                format,
+                &format!("Channels matching {}", query),
+                streams,
+            ))
+        })
+        .await?;
+
+    Ok(cached.into_response(format))
+}
+
+#[derive(Deserialize)]
+struct GetGamesQueryParams {
+    #[serde(rename = "q")]
+    query: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Lists the most watched categories, or those matching `q`.
+#[get("/-/games")]
+async fn get_games(
+    req: HttpRequest,
+    params: web::Query<GetGamesQueryParams>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    let format = Format::of(&req);
+    let limit = params.limit.unwrap_or_else(|| config.limits.list());
+    let query = params.into_inner().query;
+    let key = format!(
+        "{} /-/games?q={}&limit={}",
+        format.as_str(),
+        query.as_deref().unwrap_or(""),
+        limit
+    );
+    let auth = config.auth.clone();
+    let cached = config
+        .cache
+        .get(key, move || async move {
+            let (title, games) = match &query {
+                Some(query) => (
+                    format!("Categories matching {}", query),
+                    twch::search_games(query, limit, &auth).await?,
+                ),
+                None => (
+                    "Top categories".to_owned(),
+                    twch::get_top_games(limit, &auth).await?,
+                ),
+            };
+            Ok(match format {
+                Format::Text => games.iter().map(|game| format!("{}\n", game)).collect(),
+                Format::Html => page::list_page(
+                    &title,
+                    "/-/games",
+                    &games.iter().map(html::game_html).collect::<String>(),
+                ),
+            })
+        })
+        .await?;
+
+    Ok(cached.into_response(format))
+}
+
+#[get("/-/games/{name}")]
+async fn get_game_streams(
+    req: HttpRequest,
+    name: web::Path<String>,
+    params: web::Query<GetStreamsQueryParams>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    let format = Format::of(&req);
+    let limit = params.limit.unwrap_or_else(|| config.limits.list());
+    let name = name.into_inner();
+    let key = format!("{} /-/games/{}?limit={}", format.as_str(), name, limit);
+    let auth = config.auth.clone();
+    let cached = config
+        .cache
+        .get(key, move || async move {
+            let game = twch::get_game(&name, &auth)
+                .await?
+                .ok_or_else(|| error::ErrorNotFound(format!("Unknown game: {}", name)))?;
+            let streams = twch::get_game_streams(game.id(), limit, &auth).await?;
+            Ok(format_streams(format, game.name(), streams))
+        })
+        .await?;
+
+    Ok(cached.into_response(format))
+}
+
+/// Renders `streams` as text, or as a page titled `title`.
+fn format_streams(format: Format, title: &str, streams: Vec<TwitchStream>) -> String {
+    match format {
+        Format::Text => streams
+            .into_iter()
+            .map(|stream| format!("{}\n", stream))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Format::Html => page::list_page(
+            title,
+            "/-/search",
+            &streams.iter().map(html::stream_html).collect::<String>(),
+        ),
+    }
+}
+
+/// Address of the client, or an unspecified one if it is not known. With
+/// `trust_proxy`, it is taken from the headers set by a reverse proxy if present.
+fn peer_ip(headers: &HeaderMap, peer_addr: Option<SocketAddr>, trust_proxy: bool) -> IpAddr {
+    let forwarded = if trust_proxy {
+        throttle::forwarded_ip(headers)
+    } else {
+        None
+    };
+    forwarded
+        .or_else(|| peer_addr.map(|addr| addr.ip()))
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+/// Joins `channel`, responding with 404 if it does not exist or cannot be joined.
+async fn join_channel(
+    channel: &str,
+    config: &Config,
+) -> actix_web::Result<(ChannelInfo, TwitchChannelStream)> {
+    // Joining a nonexistent channel only fails after a timeout, so check first.
+    let info = twch::get_channel_info(channel, &config.auth)
         .await?
-        .into_iter()
-        .map(|stream| format!("{}\n", stream))
-        .collect::<Vec<_>>()
-        .join("\n");
+        .ok_or_else(|| error::ErrorNotFound(format!("Unknown channel: {}", channel)))?;
+    seed_backlog(info.login(), config).await;
 
-    Ok(HttpResponse::Ok().body(body))
+    let stream = TwitchChannelStream::new(channel)
+        .await
+        .map_err(|e| match e {
+            ChannelError::JoinFailed(_) | ChannelError::JoinTimeout(_) => {
+                error::ErrorNotFound(e.to_string())
+            }
+            _ => error::ErrorInternalServerError(e.to_string()),
+        })?;
+    Ok((info, stream))
+}
+
+/// Fills the backlog of a channel nobody has watched yet from the recent-messages
+/// service, if one is configured.
+async fn seed_backlog(channel: &str, config: &Config) {
+    let url = match &config.recent_messages_url {
+        Some(url) if config.backlog.size() > 0 && config.backlog.is_empty(channel) => url,
+        _ => return,
+    };
+    match twch::get_recent_messages(url, channel, config.backlog.size()).await {
+        Ok(events) => {
+            for event in &events {
+                config.backlog.push(event);
+            }
+        }
+        Err(e) => eprintln!("Failed to get recent messages of #{}: {}", channel, e),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChannelStreamQueryParams {
+    color: Option<ColorMode>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    timestamps: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    badges: Option<bool>,
+    width: Option<usize>,
+    filter: Option<EventFilter>,
+    backlog: Option<usize>,
+}
+
+/// Parses flags given as `1`/`0` as well as `true`/`false`.
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "1" | "true" => Ok(Some(true)),
+        "0" | "false" => Ok(Some(false)),
+        s => Err(serde::de::Error::custom(format!(
+            "invalid flag: {} (expected 1, 0, true or false)",
+            s
+        ))),
+    }
 }
 
+/// Streams the chat of `channel` as ANSI text, or serves a page showing it to
+/// browsers.
 #[get("/{channel:[a-zA-Z0-9_]+}")]
 async fn start_channel_stream(
+    req: HttpRequest,
     web::Path((channel,)): web::Path<(String,)>,
+    params: web::Query<ChannelStreamQueryParams>,
     config: web::Data<Config>,
 ) -> actix_web::Result<impl Responder> {
-    let mut stream = twch::TwitchChannelStream::new(&channel)
-        .await
-        .map_err(error::ErrorInternalServerError)?;
-    let mut interval = actix_web::rt::time::interval(config.heartbeat_interval);
+    if Format::of(&req) == Format::Html {
+        // The page opens its own stream from `/{channel}/events`
+        let info = twch::get_channel_info(&channel, &config.auth)
+            .await?
+            .ok_or_else(|| error::ErrorNotFound(format!("Unknown channel: {}", channel)))?;
+        return Ok(HttpResponse::Ok()
+            .content_type(Format::Html.content_type())
+            .header(header::VARY, "Accept")
+            .body(page::chat_page(&channel, &html::channel_info_html(&info))));
+    }
 
-    let stream =
-        futures::stream::poll_fn(move |cx| -> Poll<Option<actix_web::Result<web::Bytes>>> {
-            let mut buf = String::new();
+    let connection =
+        config
+            .streams
+            .acquire(peer_ip(req.headers(), req.peer_addr(), config.trust_proxy))?;
+    let params = params.into_inner();
+    let defaults = &config.render_options;
+    let options = RenderOptions {
+        width: params.width.or(defaults.width),
+        color: params.color.unwrap_or(defaults.color),
+        timestamps: params.timestamps.unwrap_or(defaults.timestamps),
+        badges: params.badges.unwrap_or(defaults.badges),
+        ..defaults.clone()
+    };
+    let filter = params.filter.unwrap_or_else(|| config.filter.clone());
+    let backlog = params.backlog.unwrap_or_else(|| config.backlog.size());
+    let (info, stream) = join_channel(&channel, &config).await?;
+    let room_state = stream.room_state().clone();
+    let body = relay_channel(
+        info,
+        stream,
+        connection,
+        &config,
+        filter,
+        backlog,
+        move |chunk| {
+            match chunk {
+                Chunk::Info(info) => format!("{}\n", info),
+                Chunk::Event(event) => format!("{}\n", event.display(&options)),
+                Chunk::Error(e) => format!("{}\n", e),
+                Chunk::Separator(count) => {
+                    format!("--- {} earlier messages, live from here ---\n", count)
+                }
+                Chunk::Restart(timeout) => format!(
+                    "Server restarting, reconnect in {} seconds\n",
+                    timeout.as_secs()
+                ),
+                // space + backspace
+                Chunk::Heartbeat => " \x08".to_owned(),
+            }
+        },
+    );
 
-            while let Poll::Ready(maybe_msg) = stream.poll_next_unpin(cx) {
-                match maybe_msg {
-                    Some(msg) => {
-                        buf.push_str(&msg.to_string());
-                        buf.push('\n');
-                    }
-                    None => return Poll::Ready(None),
+    let mut response = HttpResponse::Ok();
+    response
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::VARY, "Accept")
+        .header("X-Accel-Buffering", "no")
+        .header("X-Room-State", {
+            let modes = room_state.modes();
+            if modes.is_empty() {
+                "none".to_owned()
+            } else {
+                modes.join(", ")
+            }
+        });
+    if let Some(room_id) = room_state.room_id {
+        response.header("X-Room-Id", room_id.to_string());
+    }
+    Ok(response.streaming(body))
+}
+
+#[derive(Deserialize)]
+struct ChannelEventsQueryParams {
+    filter: Option<EventFilter>,
+    backlog: Option<usize>,
+}
+
+/// Streams the chat of `channel` as server-sent events of HTML fragments, for
+/// the pages served to browsers.
+#[get("/{channel:[a-zA-Z0-9_]+}/events")]
+async fn start_channel_events(
+    req: HttpRequest,
+    web::Path((channel,)): web::Path<(String,)>,
+    params: web::Query<ChannelEventsQueryParams>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    let connection =
+        config
+            .streams
+            .acquire(peer_ip(req.headers(), req.peer_addr(), config.trust_proxy))?;
+    let params = params.into_inner();
+    let filter = params.filter.unwrap_or_else(|| config.filter.clone());
+    let backlog = params.backlog.unwrap_or_else(|| config.backlog.size());
+    let (info, stream) = join_channel(&channel, &config).await?;
+    let badges = match twch::get_chat_badges(info.id(), &config.auth).await {
+        Ok(badges) => badges,
+        Err(e) => {
+            eprintln!("Failed to get chat badges: {}", e);
+            ChatBadges::default()
+        }
+    };
+    let body = relay_channel(
+        info,
+        stream,
+        connection,
+        &config,
+        filter,
+        backlog,
+        move |chunk| match chunk {
+            Chunk::Info(info) => page::sse(Some("info"), &html::channel_info_html(info)),
+            Chunk::Event(event) => page::sse(None, &html::event_html(event, &badges)),
+            Chunk::Error(e) => page::sse(
+                Some("failure"),
+                &format!(
+                    r#"<div class="event notice">{}</div>"#,
+                    html::escape(&e.to_string())
+                ),
+            ),
+            Chunk::Separator(count) => page::sse(
+                None,
+                &format!(
+                    r#"<div class="event separator">{} earlier messages, live from here</div>"#,
+                    count
+                ),
+            ),
+            Chunk::Restart(timeout) => format!(
+                "retry: {}\n{}",
+                timeout.as_millis(),
+                page::sse(
+                    Some("restart"),
+                    &format!(
+                        r#"<div class="event notice">Server restarting, reconnecting in {} seconds</div>"#,
+                        timeout.as_secs()
+                    ),
+                )
+            ),
+            Chunk::Heartbeat => ": heartbeat\n\n".to_owned(),
+        },
+    );
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header("X-Accel-Buffering", "no")
+        // Compression would buffer events
+        .encoding(ContentEncoding::Identity)
+        .streaming(body))
+}
+
+/// Serves a transparent overlay showing the chat of `channel`, e.g. as a
+/// browser source in OBS.
+#[get("/{channel:[a-zA-Z0-9_]+}/overlay")]
+async fn get_channel_overlay(
+    web::Path((channel,)): web::Path<(String,)>,
+    params: web::Query<OverlayOptions>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    twch::get_channel_info(&channel, &config.auth)
+        .await?
+        .ok_or_else(|| error::ErrorNotFound(format!("Unknown channel: {}", channel)))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(Format::Html.content_type())
+        .body(page::overlay_page(&channel, &params)))
+}
+
+/// Part of a relayed chat stream, rendered by the caller of [`relay_channel`].
+enum Chunk<'a> {
+    /// Channel info, sent first and whenever it changes
+    Info(&'a ChannelInfo),
+    /// Event that matches the filter
+    Event(&'a TwitchEvent),
+    /// Ends the given number of events sent from the backlog
+    Separator(usize),
+    /// Error that ends the stream
+    Error(&'a ChannelError),
+    /// The server is shutting down and will be back after the timeout
+    Restart(Duration),
+    /// Keeps the connection open while the chat is quiet
+    Heartbeat,
+}
+
+/// Relays `stream` to a response body, rendering each chunk with `render`.
+///
+/// Up to `backlog` recent events of the channel are sent first, followed by a
+/// separator.
+fn relay_channel(
+    info: ChannelInfo,
+    mut stream: TwitchChannelStream,
+    connection: ConnectionGuard,
+    config: &Config,
+    filter: EventFilter,
+    backlog: usize,
+    mut render: impl FnMut(Chunk) -> String + 'static,
+) -> impl Stream<Item = actix_web::Result<web::Bytes>> {
+    let channel = info.login().to_owned();
+    let mut replay = Some(config.backlog.recent(&channel, backlog, &filter));
+    let backlog = config.backlog.clone();
+    let irc_connection = config.metrics.irc_connected(&channel);
+    let metrics = config.metrics.clone();
+    let mut info_updates =
+        twch::watch_channel_info(&channel, config.info_refresh_interval, &config.auth)
+            .boxed_local();
+    let mut header = Some(info);
+    let mut interval = actix_web::rt::time::interval(config.heartbeat_interval);
+    let archiver = config.archiver.clone();
+    let mut is_closed = false;
+    let mut shutdown = config.shutdown.subscribe();
+    let shutdown_timeout = config.shutdown_timeout;
+    let mut quit_timeout: Option<Pin<Box<actix_web::rt::time::Delay>>> = None;
+
+    futures::stream::poll_fn(move |cx| -> Poll<Option<actix_web::Result<web::Bytes>>> {
+        // Keep counting the connections until the body is dropped
+        let _ = (&connection, &irc_connection);
+        if is_closed {
+            return Poll::Ready(None);
+        }
+        if let Some(timeout) = &mut quit_timeout {
+            // Wait for the IRC server to close the connection after QUIT
+            while let Poll::Ready(event) = stream.poll_next_unpin(cx) {
+                if event.is_none() {
+                    return Poll::Ready(None);
                 }
             }
+            return timeout.poll_unpin(cx).map(|_| None);
+        }
+        if shutdown.poll_unpin(cx).is_ready() {
+            if let Err(e) = stream.quit() {
+                eprintln!("Failed to leave #{}: {}", channel, e);
+            }
+            quit_timeout = Some(Box::pin(actix_web::rt::time::delay_for(QUIT_TIMEOUT)));
+            return Poll::Ready(Some(Ok(web::Bytes::from(render(Chunk::Restart(
+                shutdown_timeout,
+            ))))));
+        }
+        let mut buf = header
+            .take()
+            .map(|info| render(Chunk::Info(&info)))
+            .unwrap_or_default();
+        if let Some(replay) = replay.take().filter(|replay| !replay.is_empty()) {
+            for event in &replay {
+                buf.push_str(&render(Chunk::Event(event)));
+            }
+            buf.push_str(&render(Chunk::Separator(replay.len())));
+        }
 
-            if !buf.is_empty() {
-                return Poll::Ready(Some(Ok(web::Bytes::from(buf))));
+        while let Poll::Ready(Some(info)) = info_updates.poll_next_unpin(cx) {
+            match info {
+                Ok(info) => buf.push_str(&render(Chunk::Info(&info))),
+                Err(e) => eprintln!("Failed to refresh channel info: {}", e),
             }
+        }
 
-            match interval.poll_next_unpin(cx) {
-                Poll::Ready(_) => {
-                    // space + backspace
-                    Poll::Ready(Some(Ok(web::Bytes::from(" \x08"))))
+        while let Poll::Ready(maybe_msg) = stream.poll_next_unpin(cx) {
+            match maybe_msg {
+                Some(Ok(msg)) => {
+                    backlog.push(&msg);
+                    if filter.matches(&msg) {
+                        buf.push_str(&render(Chunk::Event(&msg)));
+                        metrics.message_relayed();
+                    }
+                    if let Some(archiver) = &archiver {
+                        archiver.archive(&msg);
+                    }
+                }
+                Some(Err(e)) => {
+                    // Headers are already sent, so tell the client why the stream ends.
+                    buf.push_str(&render(Chunk::Error(&e)));
+                    is_closed = true;
+                    break;
                 }
-                Poll::Pending => Poll::Pending,
+                None => return Poll::Ready(None),
             }
-        });
+        }
 
-    let response = HttpResponse::Ok()
-        .header(header::CACHE_CONTROL, "no-cache")
-        .header("X-Accel-Buffering", "no")
-        .streaming(stream);
-    Ok(response)
+        if !buf.is_empty() {
+            return Poll::Ready(Some(Ok(web::Bytes::from(buf))));
+        }
+
+        match interval.poll_next_unpin(cx) {
+            Poll::Ready(_) => Poll::Ready(Some(Ok(web::Bytes::from(render(Chunk::Heartbeat))))),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ChannelStatsQueryParams {
+    duration: u64,
+    limit: Option<usize>,
+}
+
+impl Default for ChannelStatsQueryParams {
+    fn default() -> Self {
+        Self {
+            duration: 10,
+            limit: None,
+        }
+    }
+}
+
+const MAX_STATS_DURATION: u64 = 60;
+
+/// How long a readiness check is reused.
+const READINESS_TTL: Duration = Duration::from_secs(5);
+
+/// Paths exempt from the request limit.
+const UNLIMITED_PATHS: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+/// How often to check whether the TLS certificate files changed.
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long to wait for the IRC server to close the connection after QUIT.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[get("/{channel:[a-zA-Z0-9_]+}/stats")]
+async fn get_channel_stats(
+    req: HttpRequest,
+    web::Path((channel,)): web::Path<(String,)>,
+    params: web::Query<ChannelStatsQueryParams>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    let _connection =
+        config
+            .streams
+            .acquire(peer_ip(req.headers(), req.peer_addr(), config.trust_proxy))?;
+    let (_, stream) = join_channel(&channel, &config).await?;
+    let _irc_connection = config.metrics.irc_connected(&channel);
+    let duration = Duration::from_secs(params.duration.min(MAX_STATS_DURATION));
+    let sampled = futures::future::select(
+        Box::pin(actix_web::rt::time::delay_for(duration)),
+        config.shutdown.subscribe(),
+    );
+
+    let mut stats = ChatStats::new();
+    stream
+        .take_until(sampled)
+        .for_each(|event| {
+            if let Ok(event) = event {
+                stats.record(&event);
+            }
+            futures::future::ready(())
+        })
+        .await;
+
+    let limit = params.limit.unwrap_or_else(|| config.limits.stats());
+    Ok(HttpResponse::Ok().json(stats.summary(limit)))
+}
+
+#[derive(Deserialize)]
+struct ChannelHistoryQueryParams {
+    q: Option<String>,
+    user: Option<String>,
+    since: Option<String>,
+    limit: Option<usize>,
+}
+
+#[get("/{channel:[a-zA-Z0-9_]+}/history")]
+async fn get_channel_history(
+    web::Path((channel,)): web::Path<(String,)>,
+    web::Query(params): web::Query<ChannelHistoryQueryParams>,
+    config: web::Data<Config>,
+) -> actix_web::Result<impl Responder> {
+    let archive = config
+        .archive
+        .clone()
+        .ok_or_else(|| error::ErrorNotFound("History is not enabled"))?;
+    let since = params
+        .since
+        .map(|since| {
+            let since = humantime::parse_duration(&since).map_err(error::ErrorBadRequest)?;
+            let since = chrono::Duration::from_std(since).map_err(error::ErrorBadRequest)?;
+            Ok::<_, actix_web::Error>(chrono::Utc::now() - since)
+        })
+        .transpose()?;
+    let query = HistoryQuery {
+        channel,
+        user: params.user,
+        text: params.q,
+        since,
+        limit: params.limit.unwrap_or_else(|| config.limits.history()),
+    };
+
+    let body = web::block(move || archive.lock().unwrap().search(&query))
+        .await
+        .map_err(error::ErrorInternalServerError)?
+        .into_iter()
+        .map(|event| format!("{}\n", event))
+        .collect::<String>();
+
+    Ok(HttpResponse::Ok().body(body))
 }
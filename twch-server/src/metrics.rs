@@ -0,0 +1,126 @@
+use twch::{HelixMetrics, LATENCY_BUCKETS};
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Counters exposed at `/metrics` in the Prometheus text format.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    irc_connections: Arc<Mutex<BTreeMap<String, usize>>>,
+    messages_relayed: Arc<AtomicU64>,
+}
+
+/// Counts as an upstream IRC connection to `channel` until dropped.
+pub struct IrcConnectionGuard {
+    metrics: Metrics,
+    channel: String,
+}
+
+impl Metrics {
+    pub fn irc_connected(&self, channel: &str) -> IrcConnectionGuard {
+        *self
+            .irc_connections
+            .lock()
+            .unwrap()
+            .entry(channel.to_owned())
+            .or_insert(0) += 1;
+        IrcConnectionGuard {
+            metrics: self.clone(),
+            channel: channel.to_owned(),
+        }
+    }
+
+    pub fn message_relayed(&self) {
+        self.messages_relayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, streaming_clients: usize, helix: &HelixMetrics) -> String {
+        // Writing to a String never fails, hence the ignored results.
+        let mut out = String::new();
+
+        describe(
+            &mut out,
+            "twch_streaming_clients",
+            "gauge",
+            "Chat streams currently open.",
+        );
+        let _ = writeln!(out, "twch_streaming_clients {}", streaming_clients);
+
+        describe(
+            &mut out,
+            "twch_irc_connections",
+            "gauge",
+            "Upstream IRC connections by channel.",
+        );
+        for (channel, count) in self.irc_connections.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "twch_irc_connections{{channel=\"{}\"}} {}",
+                channel, count
+            );
+        }
+
+        describe(
+            &mut out,
+            "twch_messages_relayed_total",
+            "counter",
+            "Chat events sent to clients.",
+        );
+        let _ = writeln!(
+            out,
+            "twch_messages_relayed_total {}",
+            self.messages_relayed.load(Ordering::Relaxed)
+        );
+
+        describe(
+            &mut out,
+            "twch_helix_requests_total",
+            "counter",
+            "Requests sent to the Helix API.",
+        );
+        let _ = writeln!(out, "twch_helix_requests_total {}", helix.requests);
+
+        describe(
+            &mut out,
+            "twch_helix_errors_total",
+            "counter",
+            "Helix requests that failed or returned an error status.",
+        );
+        let _ = writeln!(out, "twch_helix_errors_total {}", helix.errors);
+
+        let name = "twch_helix_request_duration_seconds";
+        describe(&mut out, name, "histogram", "Latency of Helix requests.");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&helix.latency_buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, helix.requests);
+        let _ = writeln!(out, "{}_sum {}", name, helix.latency_sum);
+        let _ = writeln!(out, "{}_count {}", name, helix.requests);
+
+        out
+    }
+}
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+}
+
+impl Drop for IrcConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.metrics.irc_connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.channel) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.channel);
+            }
+        }
+    }
+}
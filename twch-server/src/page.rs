@@ -0,0 +1,258 @@
+use twch::{filter::EventFilter, html::escape};
+
+use actix_web::{http::header, HttpRequest};
+use serde::Deserialize;
+
+/// Representation of a response, chosen from the `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// ANSI-colored text for terminals, the default
+    Text,
+    /// Pages for browsers
+    Html,
+}
+
+impl Format {
+    pub fn of(req: &HttpRequest) -> Self {
+        let accepts_html = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map_or(false, |accept| accept.contains("text/html"));
+        if accepts_html {
+            Self::Html
+        } else {
+            Self::Text
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Text => "text/plain; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Html => "html",
+        }
+    }
+}
+
+const STYLE: &str = r#"
+body { margin: 0; background: #18181b; color: #efeff1; font: 14px/1.5 sans-serif; }
+a { color: #bf94ff; }
+header, main { padding: 8px 16px; }
+header { border-bottom: 1px solid #333; }
+form { margin: 8px 0; }
+li { margin: 8px 0; }
+.name { font-weight: bold; }
+.login, .title, .offline { color: #adadb8; }
+.game { color: #5c9dff; }
+.live { color: #eb0400; font-weight: bold; }
+.msg, .event { padding: 2px 0; overflow-wrap: anywhere; }
+.msg.highlighted { background: #451093; }
+.event { color: #adadb8; font-style: italic; }
+.event.user-notice { color: #bf94ff; font-style: normal; font-weight: bold; }
+.event.room-state { color: #00c8af; font-style: normal; }
+.event.separator { border-bottom: 1px solid #333; text-align: center; }
+.action { font-style: italic; }
+.badge { margin-right: 4px; vertical-align: middle; }
+span.badge, .label { font-size: 11px; padding: 0 4px; border-radius: 2px; background: #333; }
+.label { margin-right: 4px; }
+.label.first { color: #00f593; }
+.label.reward { color: #00c8af; }
+.emote { height: 28px; vertical-align: middle; }
+.mention { font-weight: bold; }
+#chat { height: calc(100vh - 64px); overflow-y: auto; box-sizing: border-box; }
+"#;
+
+const CHAT_SCRIPT: &str = r#"
+const chat = document.getElementById("chat");
+const info = document.getElementById("info");
+const events = new EventSource(location.pathname + "/events" + location.search);
+function append(html) {
+  const atBottom = chat.scrollTop + chat.clientHeight >= chat.scrollHeight - 32;
+  chat.insertAdjacentHTML("beforeend", html);
+  while (chat.childElementCount > 500) chat.firstElementChild.remove();
+  if (atBottom) chat.scrollTop = chat.scrollHeight;
+}
+events.onmessage = (e) => append(e.data);
+events.addEventListener("info", (e) => { info.innerHTML = e.data; });
+events.addEventListener("restart", (e) => append(e.data));
+events.addEventListener("failure", (e) => { append(e.data); events.close(); });
+"#;
+
+const OVERLAY_STYLE: &str = r#"
+html, body { margin: 0; background: transparent; overflow: hidden; }
+#chat { position: absolute; left: 0; right: 0; bottom: 0; padding: 8px; color: #fff;
+  text-shadow: 0 0 2px #000, 0 0 4px #000; }
+.msg, .event { padding: 2px 0; overflow-wrap: anywhere; transition: opacity 1s; }
+.msg.highlighted { background: rgba(69, 16, 147, 0.6); }
+.event { font-style: italic; }
+.name { font-weight: bold; }
+.action { font-style: italic; }
+.badge, .emote { height: 1.2em; margin-right: 4px; vertical-align: middle; }
+span.badge, .label { display: none; }
+.mention { font-weight: bold; }
+.separator { display: none; }
+.faded { opacity: 0; }
+"#;
+
+const OVERLAY_SCRIPT: &str = r#"
+const chat = document.getElementById("chat");
+const { events, lines, lifetime, fade, font } = chat.dataset;
+chat.style.fontFamily = font;
+const source = new EventSource(events);
+source.onmessage = (e) => {
+  chat.insertAdjacentHTML("beforeend", e.data);
+  while (chat.childElementCount > Number(lines)) chat.firstElementChild.remove();
+  if (Number(lifetime) > 0) {
+    for (const line of chat.querySelectorAll(":scope > :not([data-shown])")) {
+      line.dataset.shown = "";
+      line.style.transitionDuration = fade + "s";
+      setTimeout(() => line.classList.add("faded"), Number(lifetime) * 1000);
+      setTimeout(() => line.remove(), (Number(lifetime) + Number(fade)) * 1000);
+    }
+  }
+};
+// The server closes the stream for good on failures, so try again later.
+source.addEventListener("failure", () => {
+  source.close();
+  setTimeout(() => location.reload(), 30000);
+});
+"#;
+
+/// Appearance of the chat overlay, set from the query string.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct OverlayOptions {
+    /// CSS font family
+    pub font: String,
+    /// Font size in pixels
+    pub size: u32,
+    /// Seconds messages stay visible, or 0 to keep them
+    pub lifetime: u32,
+    /// Seconds messages take to fade out
+    pub fade: f32,
+    /// Maximum number of messages shown
+    pub lines: usize,
+    /// Only show messages of these kinds
+    pub filter: Option<EventFilter>,
+}
+
+impl Default for OverlayOptions {
+    fn default() -> Self {
+        Self {
+            font: "sans-serif".to_owned(),
+            size: 20,
+            lifetime: 30,
+            fade: 1.0,
+            lines: 10,
+            filter: None,
+        }
+    }
+}
+
+/// Wraps `body`, which must already be escaped, in a full HTML document.
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{} - twch</title>
+<style>{}</style>
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+/// Page showing the live chat of `channel`, fed by server-sent events.
+pub fn chat_page(channel: &str, info_html: &str) -> String {
+    page(
+        channel,
+        &format!(
+            r#"<header id="info">{}</header>
+<main id="chat"></main>
+<script>{}</script>"#,
+            info_html, CHAT_SCRIPT
+        ),
+    )
+}
+
+/// Transparent page showing the recent chat of `channel`, for streaming
+/// software to embed.
+pub fn overlay_page(channel: &str, options: &OverlayOptions) -> String {
+    let mut events = format!("/{}/events", channel);
+    if let Some(filter) = &options.filter {
+        events.push_str("?filter=");
+        events.push_str(&filter.to_string());
+    }
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{} - twch overlay</title>
+<style>{}</style>
+</head>
+<body>
+<main id="chat" style="font-size: {}px" data-events="{}" data-lines="{}" data-lifetime="{}" data-fade="{}" data-font="{}"></main>
+<script>{}</script>
+</body>
+</html>
+"#,
+        escape(channel),
+        OVERLAY_STYLE,
+        options.size,
+        escape(&events),
+        options.lines,
+        options.lifetime,
+        options.fade.max(0.0),
+        escape(&options.font),
+        OVERLAY_SCRIPT
+    )
+}
+
+/// Page listing `items`, which are `<li>` elements, with a search form.
+pub fn list_page(title: &str, search_action: &str, items: &str) -> String {
+    page(
+        title,
+        &format!(
+            r#"<header><a href="/">twch</a> · <a href="/-/games">games</a>
+<form action="{}"><input name="q" placeholder="Search" aria-label="Search"></form></header>
+<main><h1>{}</h1><ul>{}</ul></main>"#,
+            escape(search_action),
+            escape(title),
+            items
+        ),
+    )
+}
+
+/// Formats a server-sent event, which is a plain message if `event` is `None`.
+pub fn sse(event: Option<&str>, data: &str) -> String {
+    let mut s = String::new();
+    if let Some(event) = event {
+        s.push_str("event: ");
+        s.push_str(event);
+        s.push('\n');
+    }
+    for line in data.lines() {
+        s.push_str("data: ");
+        s.push_str(line);
+        s.push('\n');
+    }
+    s.push('\n');
+    s
+}
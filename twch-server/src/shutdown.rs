@@ -0,0 +1,39 @@
+use futures::channel::oneshot;
+use std::sync::{Arc, Mutex};
+
+/// Tells open streams that the server is shutting down.
+#[derive(Clone)]
+pub struct Shutdown {
+    // `None` once shutdown has started
+    listeners: Arc<Mutex<Option<Vec<oneshot::Sender<()>>>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            listeners: Arc::new(Mutex::new(Some(Vec::new()))),
+        }
+    }
+}
+
+impl Shutdown {
+    /// Returns a future that completes, with either result, when shutdown
+    /// starts.
+    pub fn subscribe(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        if let Some(listeners) = self.listeners.lock().unwrap().as_mut() {
+            listeners.retain(|listener| !listener.is_canceled());
+            listeners.push(tx);
+        }
+        // Otherwise `tx` is dropped here, completing `rx` right away.
+        rx
+    }
+
+    pub fn trigger(&self) {
+        if let Some(listeners) = self.listeners.lock().unwrap().take() {
+            for listener in listeners {
+                let _ = listener.send(());
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use actix_web::{
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    HttpResponse, ResponseError,
+};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Buckets idle for this long are full again, so they can be forgotten.
+const BUCKET_IDLE: Duration = Duration::from_secs(60);
+
+/// Forget idle buckets once this many addresses are tracked.
+const MAX_TRACKED_ADDRS: usize = 10_000;
+
+/// How long clients rejected for having too many streams are told to wait.
+const STREAM_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Rejection of a client that exceeded a limit, responded with 429 and
+/// `Retry-After`.
+#[derive(Debug)]
+pub struct TooManyRequests {
+    message: String,
+    retry_after: Duration,
+}
+
+impl Display for TooManyRequests {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for TooManyRequests {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .header(header::RETRY_AFTER, self.retry_after.as_secs().to_string())
+            .content_type("text/plain; charset=utf-8")
+            .body(format!("{}\n", self.message))
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-address token bucket allowing `per_minute` requests a minute, in bursts
+/// of up to `per_minute`.
+#[derive(Clone)]
+pub struct RequestLimiter {
+    per_minute: u32,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+impl RequestLimiter {
+    /// `per_minute` of 0 disables the limit.
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn check(&self, addr: IpAddr) -> Result<(), TooManyRequests> {
+        self.check_at(addr, Instant::now())
+    }
+
+    fn check_at(&self, addr: IpAddr, now: Instant) -> Result<(), TooManyRequests> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(self.per_minute);
+        let per_sec = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_ADDRS {
+            buckets.retain(|_, bucket| now - bucket.updated < BUCKET_IDLE);
+        }
+        let bucket = buckets.entry(addr).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = (now - bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(TooManyRequests {
+            message: format!(
+                "Too many requests, the limit is {} per minute",
+                self.per_minute
+            ),
+            retry_after: Duration::from_secs_f64(((1.0 - bucket.tokens) / per_sec).ceil()),
+        })
+    }
+}
+
+/// Client address added by the nearest proxy, i.e. the last entry of
+/// `Forwarded` or else `X-Forwarded-For`. Earlier entries are sent by the
+/// client, so they can be anything.
+///
+/// A header repeated on several lines is ignored, because `HeaderMap` does not
+/// keep the order of its values.
+pub fn forwarded_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    let last_entry = |name: HeaderName| {
+        let mut values = headers.get_all(name);
+        match (values.next(), values.next()) {
+            (Some(value), None) => value.to_str().ok()?.rsplit(',').next(),
+            _ => None,
+        }
+    };
+    match last_entry(header::FORWARDED) {
+        Some(element) => element
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+            .and_then(|(_, addr)| parse_forwarded_ip(addr)),
+        None => last_entry(HeaderName::from_static("x-forwarded-for")).and_then(parse_forwarded_ip),
+    }
+}
+
+/// Address in a `Forwarded` or `X-Forwarded-For` entry, which may be quoted
+/// and include a port.
+fn parse_forwarded_ip(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim().trim_matches('"');
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.trim_matches(|c| c == '[' || c == ']').parse())
+        .ok()
+}
+
+#[derive(Default)]
+struct Connections {
+    total: usize,
+    per_addr: HashMap<IpAddr, usize>,
+}
+
+/// Caps the number of concurrent streams, in total and per address.
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    max: usize,
+    max_per_addr: usize,
+    connections: Arc<Mutex<Connections>>,
+}
+
+/// Counts as an open stream until dropped.
+pub struct ConnectionGuard {
+    limiter: ConnectionLimiter,
+    addr: IpAddr,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max: usize, max_per_addr: usize) -> Self {
+        Self {
+            max,
+            max_per_addr,
+            connections: Default::default(),
+        }
+    }
+
+    /// Number of streams currently open.
+    pub fn active(&self) -> usize {
+        self.connections.lock().unwrap().total
+    }
+
+    pub fn acquire(&self, addr: IpAddr) -> Result<ConnectionGuard, TooManyRequests> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.per_addr.get(&addr).copied().unwrap_or(0);
+        let message = if count >= self.max_per_addr {
+            format!(
+                "Too many streams from your address (max {}), close one and retry",
+                self.max_per_addr
+            )
+        } else if connections.total >= self.max {
+            "The server is streaming to too many clients, retry later".to_owned()
+        } else {
+            connections.total += 1;
+            *connections.per_addr.entry(addr).or_insert(0) += 1;
+            return Ok(ConnectionGuard {
+                limiter: self.clone(),
+                addr,
+            });
+        };
+        Err(TooManyRequests {
+            message,
+            retry_after: STREAM_RETRY_AFTER,
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.limiter.connections.lock().unwrap();
+        connections.total -= 1;
+        if let Some(count) = connections.per_addr.get_mut(&self.addr) {
+            *count -= 1;
+            if *count == 0 {
+                connections.per_addr.remove(&self.addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const A: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const B: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn refill_request_buckets() {
+        let limiter = RequestLimiter::new(60);
+        let start = Instant::now();
+        for _ in 0..60 {
+            assert!(limiter.check_at(A, start).is_ok());
+        }
+        let e = limiter.check_at(A, start).unwrap_err();
+        assert_eq!(e.retry_after, Duration::from_secs(1));
+        assert!(limiter.check_at(B, start).is_ok());
+
+        // One token a second
+        let later = start + Duration::from_millis(1500);
+        assert!(limiter.check_at(A, later).is_ok());
+        let e = limiter.check_at(A, later).unwrap_err();
+        assert_eq!(e.retry_after, Duration::from_secs(1));
+
+        // Never more than the burst
+        let much_later = start + Duration::from_secs(3600);
+        for _ in 0..60 {
+            assert!(limiter.check_at(A, much_later).is_ok());
+        }
+        assert!(limiter.check_at(A, much_later).is_err());
+    }
+
+    #[test]
+    fn round_up_retry_after() {
+        let limiter = RequestLimiter::new(1);
+        let start = Instant::now();
+        assert!(limiter.check_at(A, start).is_ok());
+        let e = limiter
+            .check_at(A, start + Duration::from_millis(500))
+            .unwrap_err();
+        assert_eq!(e.retry_after, Duration::from_secs(60));
+
+        let response = e.error_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "60");
+    }
+
+    #[test]
+    fn unlimited_requests() {
+        let limiter = RequestLimiter::new(0);
+        let start = Instant::now();
+        for _ in 0..1000 {
+            assert!(limiter.check_at(A, start).is_ok());
+        }
+    }
+
+    #[test]
+    fn release_connections_on_drop() {
+        let limiter = ConnectionLimiter::new(3, 2);
+        let a1 = limiter.acquire(A).unwrap();
+        let a2 = limiter.acquire(A).unwrap();
+        let e = limiter.acquire(A).err().unwrap();
+        assert_eq!(e.retry_after, STREAM_RETRY_AFTER);
+
+        let b1 = limiter.acquire(B).unwrap();
+        assert!(limiter.acquire(B).is_err(), "total limit reached");
+        assert_eq!(limiter.active(), 3);
+
+        drop(a1);
+        assert_eq!(limiter.active(), 2);
+        let _a3 = limiter.acquire(A).unwrap();
+        drop((a2, b1));
+        assert_eq!(limiter.active(), 1);
+        assert!(!limiter
+            .connections
+            .lock()
+            .unwrap()
+            .per_addr
+            .contains_key(&B));
+    }
+
+    #[test]
+    fn parse_forwarded_ips() {
+        let v6 = "2001:db8::1".parse().ok();
+        assert_eq!(parse_forwarded_ip("192.0.2.1"), Some(A));
+        assert_eq!(parse_forwarded_ip("192.0.2.1:1234"), Some(A));
+        assert_eq!(parse_forwarded_ip("2001:db8::1"), v6);
+        assert_eq!(parse_forwarded_ip("[2001:db8::1]"), v6);
+        assert_eq!(parse_forwarded_ip("[2001:db8::1]:1234"), v6);
+        assert_eq!(parse_forwarded_ip("\"[2001:db8::1]:1234\""), v6);
+        assert_eq!(parse_forwarded_ip(" 192.0.2.1"), Some(A));
+        assert_eq!(parse_forwarded_ip("unknown"), None);
+    }
+
+    #[test]
+    fn trust_only_the_last_forwarded_entry() {
+        let headers = |pairs: &[(&str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.append(
+                    HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    value.parse().unwrap(),
+                );
+            }
+            headers
+        };
+
+        // The client sends a spoofed entry, the proxy appends the real address
+        let spoofed = headers(&[("x-forwarded-for", "198.51.100.7, 192.0.2.1")]);
+        assert_eq!(forwarded_ip(&spoofed), Some(A));
+        let repeated = headers(&[
+            ("x-forwarded-for", "198.51.100.7"),
+            ("x-forwarded-for", "192.0.2.2"),
+        ]);
+        assert_eq!(forwarded_ip(&repeated), None);
+
+        let forwarded = headers(&[(
+            "forwarded",
+            "for=198.51.100.7, for=\"[2001:db8::1]:1234\";proto=https",
+        )]);
+        assert_eq!(forwarded_ip(&forwarded), "2001:db8::1".parse().ok());
+        let forwarded = headers(&[
+            ("forwarded", "for=192.0.2.1"),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+        assert_eq!(forwarded_ip(&forwarded), Some(A));
+
+        assert_eq!(forwarded_ip(&HeaderMap::new()), None);
+    }
+}
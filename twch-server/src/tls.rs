@@ -0,0 +1,92 @@
+use anyhow::Context;
+use rustls::{
+    internal::pemfile,
+    sign::{self, CertifiedKey},
+    ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig,
+};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+/// Certificate chain and private key read from PEM files, which can be
+/// replaced while the server runs.
+pub struct CertReloader {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<(CertifiedKey, Option<SystemTime>)>,
+}
+
+impl CertReloader {
+    pub fn load(cert_path: &Path, key_path: &Path) -> anyhow::Result<Arc<Self>> {
+        let modified = modified(cert_path, key_path);
+        let key = load_certified_key(cert_path, key_path)?;
+        Ok(Arc::new(Self {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            current: RwLock::new((key, modified)),
+        }))
+    }
+
+    /// TLS settings serving the current certificate.
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = self.clone();
+        config
+    }
+
+    /// Reads the files again if either was modified since they were last
+    /// read. Returns whether the certificate was replaced.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let modified = modified(&self.cert_path, &self.key_path);
+        if modified.is_none() || modified == self.current.read().unwrap().1 {
+            return Ok(false);
+        }
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = (key, modified);
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for CertReloader {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().0.clone())
+    }
+}
+
+/// Latest modification time of the two files, if both can be read.
+fn modified(cert_path: &Path, key_path: &Path) -> Option<SystemTime> {
+    let cert = cert_path.metadata().and_then(|m| m.modified()).ok()?;
+    let key = key_path.metadata().and_then(|m| m.modified()).ok()?;
+    Some(cert.max(key))
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> anyhow::Result<CertifiedKey> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to read {}", path.display()))
+    };
+
+    let certs = pemfile::certs(&mut open(cert_path)?)
+        .ok()
+        .filter(|certs| !certs.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("No certificates in {}", cert_path.display()))?;
+
+    // Keys are either PKCS #8 ("BEGIN PRIVATE KEY") or PKCS #1 ("BEGIN RSA
+    // PRIVATE KEY").
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?).unwrap_or_default();
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("No private key in {}", key_path.display()))?;
+    let key = sign::any_supported_type(key)
+        .map_err(|_| anyhow::anyhow!("Unsupported private key in {}", key_path.display()))?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}